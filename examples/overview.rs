@@ -3,7 +3,8 @@ extern crate ibapi;
 
 use std::env::args;
 
-use ibapi::ib::{Contract, Message, Request};
+use ibapi::client::Client;
+use ibapi::socket::Socket;
 
 fn main() {
     env_logger::init();
@@ -12,23 +13,12 @@ fn main() {
 
     let addr = args.get(1).map_or("127.0.0.1:7496", |s| s.as_str());
 
-    let ib = ibapi::socket::Socket::connect(addr);
+    let ib = Socket::connect(addr).expect("Cannot connect to TWS");
+    let client = Client::new(ib);
 
-    ib.request(Request::ReqPositions);
-
-    for msg in ib.rx {
-        use Message::*;
-
-        match msg {
-            PositionData { account: acc, contract, position, avg_cost, .. } => {
-                let sym = contract.local_symbol;
-                let val = position * avg_cost;
-                println!("{} {:22} {:8} {:8.2} {:10.2}", acc, sym, position, avg_cost, val);
-            },
-            PositionDataEnd { .. } => break,
-            _ => {
-                println!(">>> [IB] {:?}", msg);
-            }
-        }
+    for pos in client.positions().expect("Cannot get positions") {
+        let sym = pos.contract.local_symbol;
+        let val = pos.position * pos.avg_cost;
+        println!("{} {:22} {:8} {:8.2} {:10.2}", pos.account, sym, pos.position, pos.avg_cost, val);
     }
 }
@@ -0,0 +1,683 @@
+//! Client on tokio, enabled with the `async` feature.
+//!
+//! Uses the same wire format, response routing and historical data pacing as
+//! `client::Client`, but with one reader and one writer task instead of OS
+//! threads.
+
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use log::{error, info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, Notify};
+use tokio::time;
+
+use crate::client::{HistoricalQuery, OrderIdAllocator, RequestError};
+use crate::client::historical::{HistoricalAttempts, HistoricalPacing, Step};
+use crate::client::requests::Collected;
+use crate::client::routes::{is_streaming, Collect, RouteTable};
+use crate::client::subscription::{self, Tick, Update};
+use crate::ib::{Bar, Contract, ContractDetails, Hello, Message, OpenOrder, Position, Request};
+use crate::protocol;
+use crate::socket::{decode_frame, unsupported_request, ConnectError, ConnectOptions, Pacer, WriterMetrics, WriterStats};
+
+type Routes = Arc<RouteTable<mpsc::UnboundedSender<Message>>>;
+
+pub struct AsyncClient {
+    requests: mpsc::UnboundedSender<Request>,
+    routes: Routes,
+    next_request_id: AtomicI32,
+    order_ids: Arc<OrderIdAllocator>,
+    order_ids_seeded: Arc<Notify>,
+    historical: HistoricalPacing,
+    writer_metrics: Arc<WriterMetrics>,
+    timeout: Duration,
+    /// Negotiated in the handshake
+    pub server_version: u64,
+    /// Messages not routed to a request
+    pub messages: Responses,
+}
+
+/// Stream of messages, e.g. the responses to a single request. Ends after the
+/// *End message or a request error.
+///
+/// `request_id` is -1 for `AsyncClient::messages`.
+pub struct Responses {
+    pub request_id: i32,
+    rx: mpsc::UnboundedReceiver<Message>,
+}
+
+impl Responses {
+    pub async fn next(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for Responses {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Updates of a streaming request, like `client::Subscription`. The stream
+/// ends when the request ends or fails, see `error`.
+///
+/// Dropping the subscription sends the matching cancel request, unless the
+/// request has ended already, and stops routing its messages.
+pub struct Subscription<T> {
+    request_id: i32,
+    rx: mpsc::UnboundedReceiver<Message>,
+    routes: Routes,
+    requests: mpsc::UnboundedSender<Request>,
+    cancel: Option<Request>,
+    error: Option<RequestError>,
+    update: PhantomData<fn() -> T>,
+}
+
+impl<T: Update> Subscription<T> {
+    pub fn request_id(&self) -> i32 {
+        self.request_id
+    }
+
+    /// Error that ended the subscription, if any
+    pub fn error(&self) -> Option<&RequestError> {
+        self.error.as_ref()
+    }
+
+    pub async fn next(&mut self) -> Option<T> {
+        while let Some(msg) = self.rx.recv().await {
+            if let Some(update) = self.update(msg) {
+                return Some(update);
+            }
+        }
+        None
+    }
+
+    /// Same as dropping the subscription
+    pub fn cancel(self) {}
+
+    fn update(&mut self, msg: Message) -> Option<T> {
+        subscription::update(Some(self.request_id), msg, &mut self.cancel, &mut self.error)
+    }
+}
+
+impl<T: Update> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(msg)) => if let Some(update) = self.update(msg) {
+                    return Poll::Ready(Some(update));
+                },
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            // Only fails if the connection is gone, and the request with it
+            let _ = self.requests.send(cancel);
+        }
+        self.routes.remove(self.request_id);
+    }
+}
+
+impl AsyncClient {
+    /// Connects and performs the handshake as `ConnectOptions::connect`, then
+    /// spawns the reader and writer tasks on the current runtime. Requests
+    /// are paced by `ConnectOptions::rate_limit`.
+    ///
+    /// The connection is not supervised: when it drops, `messages` receives
+    /// `Message::Disconnected` and ends, all `Responses` and subscriptions end,
+    /// and further requests fail with `RequestError::Disconnected`. A new
+    /// client has to be connected. `ConnectOptions::reconnect_backoff` and
+    /// `thread_names` don't apply.
+    pub async fn connect<A: ToSocketAddrs>(addr: A, options: &ConnectOptions) -> Result<AsyncClient, ConnectError> {
+        let connect = TcpStream::connect(addr);
+        let stream = match options.connect_timeout {
+            Some(timeout) => time::timeout(timeout, connect).await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??,
+            None => connect.await?,
+        };
+        stream.set_nodelay(options.nodelay)?;
+        socket2::SockRef::from(&stream).set_keepalive(options.keepalive)?;
+
+        info!("Connected");
+
+        AsyncClient::connect_stream(stream, options).await
+    }
+
+    /// Like `connect` over a stream that is connected already, e.g. a Unix
+    /// socket or a TLS stream. `ConnectOptions::connect_timeout`, `nodelay`
+    /// and `keepalive` don't apply.
+    pub async fn connect_stream<S>(mut stream: S, options: &ConnectOptions) -> Result<AsyncClient, ConnectError>
+        where S: AsyncRead + AsyncWrite + Send + Unpin + 'static
+    {
+        let server_version = handshake(&mut stream, options).await?;
+        let (reader, writer) = tokio::io::split(stream);
+
+        let routes = Routes::default();
+        let order_ids = Arc::new(OrderIdAllocator::default());
+        let order_ids_seeded = Arc::new(Notify::new());
+        let (unrouted_tx, unrouted_rx) = mpsc::unbounded_channel();
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let (pacer, writer_metrics) = options.pacer();
+
+        let dispatcher = Arc::new(Dispatcher {
+            routes: routes.clone(),
+            order_ids: order_ids.clone(),
+            order_ids_seeded: order_ids_seeded.clone(),
+            unrouted: Mutex::new(Some(unrouted_tx)),
+        });
+        let writer = tokio::spawn(write_loop(writer, requests_rx, dispatcher.clone(), server_version, pacer));
+        tokio::spawn(async move {
+            read_loop(BufReader::new(reader), server_version, &dispatcher).await;
+
+            // Requests fail once the writer is gone, and routes registered
+            // before are closed here
+            writer.abort();
+            let _ = writer.await;
+            dispatcher.close();
+        });
+
+        Ok(AsyncClient {
+            requests: requests_tx,
+            routes,
+            next_request_id: AtomicI32::new(1),
+            order_ids,
+            order_ids_seeded,
+            historical: HistoricalPacing::default(),
+            writer_metrics,
+            timeout: Duration::from_secs(30),
+            server_version,
+            messages: Responses { request_id: -1, rx: unrouted_rx },
+        })
+    }
+
+    /// Time to wait for all responses to a request, 30s by default.
+    /// `historical_data` uses `PacingLimits::timeout` instead.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Like `Socket::writer_stats`. Requests not yet taken up by the writer
+    /// task are not counted in `queue_depth`.
+    pub fn writer_stats(&self) -> WriterStats {
+        self.writer_metrics.stats()
+    }
+
+    pub fn next_request_id(&self) -> i32 {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Next order id for `PlaceOrder`, like `Client::next_order_id`
+    pub async fn next_order_id(&self) -> Result<i32, RequestError> {
+        if !self.order_ids.is_seeded() {
+            self.send(Request::ReqIds { num_ids: 1 });
+        }
+
+        let deadline = time::Instant::now() + self.timeout;
+
+        loop {
+            let seeded = self.order_ids_seeded.notified();
+
+            if let Some(id) = self.order_ids.next_timeout(Duration::default()) {
+                return Ok(id);
+            }
+
+            time::timeout_at(deadline, seeded).await.map_err(|_| RequestError::Timeout)?;
+        }
+    }
+
+    /// Sends a request without routing, responses arrive on `messages`
+    pub fn send(&self, request: Request) {
+        // Only fails if the connection is gone, which `messages` reports
+        let _ = self.try_send(request);
+    }
+
+    fn try_send(&self, request: Request) -> Result<(), RequestError> {
+        self.requests.send(request).map_err(|_| RequestError::Disconnected)
+    }
+
+    /// Allocates a request id, builds the request with it and sends it. The
+    /// responses end at once if the connection is gone.
+    pub fn request<F: FnOnce(i32) -> Request>(&self, build: F) -> Responses {
+        let request_id = self.next_request_id();
+        let request = build(request_id);
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.insert(request_id, tx, is_streaming(&request));
+        if self.try_send(request).is_err() {
+            self.unregister(request_id);
+        }
+
+        Responses { request_id, rx }
+    }
+
+    /// Stops routing `request_id`, further messages for it go to `messages`
+    pub fn unregister(&self, request_id: i32) {
+        self.routes.remove(request_id);
+    }
+
+    fn collect(&self, kind: Collect) -> mpsc::UnboundedReceiver<Message> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.collect(kind, tx);
+        rx
+    }
+
+    /// All contracts matching `contract`, like `Client::contract_details`
+    /// but not served from a `Cache`
+    pub async fn contract_details(&self, contract: &Contract) -> Result<Vec<ContractDetails>, RequestError> {
+        let mut responses = self.request(|req_id| Request::ReqContractData { req_id, contract: contract.clone() });
+
+        match collect(&mut responses.rx, self.timeout).await {
+            Ok(collected) => Ok(collected.contract_details()),
+            Err(err) => {
+                self.unregister(responses.request_id);
+                Err(err)
+            },
+        }
+    }
+
+    /// Positions of all accounts. Cancels the positions subscription afterwards.
+    pub async fn positions(&self) -> Result<Vec<Position>, RequestError> {
+        let mut rx = self.collect(Collect::Positions);
+        self.try_send(Request::ReqPositions)?;
+
+        let collected = collect(&mut rx, self.timeout).await;
+        self.send(Request::CancelPositions);
+
+        Ok(collected?.positions())
+    }
+
+    /// Open orders placed by this client, see `Request::ReqOpenOrders`
+    pub async fn open_orders(&self) -> Result<Vec<OpenOrder>, RequestError> {
+        let mut rx = self.collect(Collect::OpenOrders);
+        self.try_send(Request::ReqOpenOrders)?;
+
+        Ok(collect(&mut rx, self.timeout).await?.open_orders())
+    }
+
+    /// Sends the request built with a new id and cancels it with the request
+    /// built by `cancel` when the subscription is dropped
+    fn subscribe<T, F, C>(&self, build: F, cancel: C) -> Subscription<T>
+        where F: FnOnce(i32) -> Request, C: FnOnce(i32) -> Request
+    {
+        let responses = self.request(build);
+
+        Subscription {
+            request_id: responses.request_id,
+            rx: responses.rx,
+            routes: self.routes.clone(),
+            requests: self.requests.clone(),
+            cancel: Some(cancel(responses.request_id)),
+            error: None,
+            update: PhantomData,
+        }
+    }
+
+    /// Streaming market data, or a single snapshot that ends the subscription
+    /// after `TickSnapshotEnd`
+    pub fn market_data(&self, contract: &Contract, generic_tick_list: &str, snapshot: bool) -> Subscription<Tick> {
+        self.subscribe(
+            |ticker_id| Request::ReqMktData {
+                ticker_id,
+                contract: contract.clone(),
+                generic_tick_list: generic_tick_list.into(),
+                snapshot,
+                regulatory_snapshot: false,
+                mkt_data_options: Vec::new(),
+            },
+            |ticker_id| Request::CancelMktData { ticker_id })
+    }
+
+    /// Like `Client::historical_data`: paced, and retried after pacing violations
+    pub async fn historical_data(&self, query: &HistoricalQuery) -> Result<Vec<Bar>, RequestError> {
+        let mut attempts = HistoricalAttempts::new(&self.historical);
+
+        loop {
+            time::sleep(attempts.start(query)).await;
+
+            let mut responses = self.request(|id| query.request(id));
+            let deadline = time::Instant::now() + attempts.timeout();
+
+            loop {
+                let step = match time::timeout_at(deadline, responses.next()).await {
+                    Ok(Some(msg)) => attempts.step(responses.request_id, msg),
+                    Ok(None) => return Err(RequestError::Disconnected),
+                    Err(_) => {
+                        self.unregister(responses.request_id);
+                        self.send(Request::CancelHistoricalData { ticker_id: responses.request_id });
+                        return Err(RequestError::Timeout);
+                    },
+                };
+
+                match step {
+                    Step::Wait => {},
+                    Step::Retry => break,
+                    Step::Done(result) => return result,
+                }
+            }
+        }
+    }
+}
+
+/// Receives up to and including the *End message, or fails on the first
+/// request error or after `timeout`.
+async fn collect(rx: &mut mpsc::UnboundedReceiver<Message>, timeout: Duration) -> Result<Collected, RequestError> {
+    let deadline = time::Instant::now() + timeout;
+    let mut collected = Collected::default();
+
+    loop {
+        match time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(msg)) => if collected.push(msg)? {
+                return Ok(collected);
+            },
+            Ok(None) => return Err(RequestError::Disconnected),
+            Err(_) => return Err(RequestError::Timeout),
+        }
+    }
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, options: &ConnectOptions) -> Result<u64, ConnectError> {
+    stream.write_all(&options.handshake_prefix()).await?;
+
+    let frame = match options.handshake_timeout {
+        Some(timeout) => time::timeout(timeout, read_frame(stream)).await
+            .map_err(|_| ConnectError::HandshakeTimeout)??,
+        None => read_frame(stream).await?,
+    };
+
+    let hello: Hello = protocol::from_frame(&frame, 0)
+        .map_err(ConnectError::Handshake)?;
+    info!("{:?}", hello);
+
+    let server_version = hello.server_version;
+
+    write_request(stream, &options.start_api(), server_version).await?;
+    info!("Sent START_API");
+
+    Ok(server_version)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u32().await?;
+
+    let mut frame = vec![0; len as usize];
+    reader.read_exact(&mut frame).await?;
+
+    Ok(frame)
+}
+
+async fn write_request<W: AsyncWrite + Unpin>(writer: &mut W, request: &Request, server_version: u64) -> Result<(), ConnectError> {
+    let msg = protocol::to_bytes(request, server_version)?;
+    writer.write_u32(msg.len() as u32).await?;
+    writer.write_all(&msg).await?;
+    Ok(())
+}
+
+async fn read_loop<R: AsyncRead + Unpin>(mut reader: R, server_version: u64, dispatcher: &Dispatcher) {
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(frame) => frame,
+            Err(err) => {
+                error!("Read error: {}", err);
+                break;
+            }
+        };
+
+        dispatcher.dispatch(decode_frame(&frame, server_version));
+    }
+}
+
+/// Like the `Socket` writer thread: requests are released by the `Pacer`,
+/// and those still queued when the client is dropped are written before
+/// shutting down the stream. Exits on the first I/O error.
+async fn write_loop<W: AsyncWrite + Unpin>(mut writer: W, mut requests: mpsc::UnboundedReceiver<Request>, dispatcher: Arc<Dispatcher>, server_version: u64, mut pacer: Pacer) {
+    loop {
+        // Requests that arrived meanwhile are queued first, so cancels can
+        // still overtake them
+        let request = match pacer.wait_time() {
+            Some(wait) => match time::timeout(wait, requests.recv()).await {
+                Ok(request) => request,
+                Err(_) => {
+                    if let Some(request) = pacer.pop() {
+                        if write(&mut writer, &request, &dispatcher, server_version).await.is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                },
+            },
+            None => requests.recv().await,
+        };
+
+        match request {
+            Some(request) => pacer.push(request),
+            None => break,
+        }
+    }
+
+    while let Some(wait) = pacer.wait_time() {
+        time::sleep(wait).await;
+        if let Some(request) = pacer.pop() {
+            if write(&mut writer, &request, &dispatcher, server_version).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    if let Err(err) = writer.shutdown().await {
+        warn!("Shutdown error: {}", err);
+    }
+}
+
+/// Requests that cannot be encoded are logged and answered like
+/// `unsupported_request`. Fails on I/O errors.
+async fn write<W: AsyncWrite + Unpin>(writer: &mut W, request: &Request, dispatcher: &Dispatcher, server_version: u64) -> Result<(), ()> {
+    match write_request(writer, request, server_version).await {
+        Ok(()) => Ok(()),
+        Err(ConnectError::Encode(err)) => {
+            error!("Cannot encode {:?}: {}", request, err);
+            if let Some(msg) = unsupported_request(request, &err) {
+                dispatcher.dispatch(msg);
+            }
+            Ok(())
+        },
+        Err(err) => {
+            error!("Write error: {}", err);
+            warn!("Request not sent: {:?}", request);
+            Err(())
+        },
+    }
+}
+
+struct Dispatcher {
+    routes: Routes,
+    order_ids: Arc<OrderIdAllocator>,
+    order_ids_seeded: Arc<Notify>,
+    /// None once closed, which ends `AsyncClient::messages`
+    unrouted: Mutex<Option<mpsc::UnboundedSender<Message>>>,
+}
+
+impl Dispatcher {
+    fn dispatch(&self, msg: Message) {
+        if let Message::NextValidId { order_id, .. } = msg {
+            self.order_ids.seed(order_id);
+            self.order_ids_seeded.notify_waiters();
+        }
+
+        if let Some(msg) = self.routes.dispatch(msg) {
+            if let Some(unrouted) = &*self.unrouted.lock().unwrap() {
+                // Nobody listening for unrouted messages is fine
+                let _ = unrouted.send(msg);
+            }
+        }
+    }
+
+    /// Ends all `Responses` and, after `Disconnected`, `AsyncClient::messages`
+    fn close(&self) {
+        self.routes.clear();
+        if let Some(unrouted) = self.unrouted.lock().unwrap().take() {
+            let _ = unrouted.send(Message::Disconnected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::runtime::{Builder, Runtime};
+    use tokio::time;
+
+    use crate::client::{RequestError, Tick};
+    use crate::ib::{Message, Request};
+    use crate::protocol;
+    use crate::socket::ConnectOptions;
+    use super::{read_frame, AsyncClient};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    /// Server side of a connection, like `socket::pipe::FakeGateway`
+    struct Gateway {
+        stream: DuplexStream,
+        start_api: Vec<String>,
+    }
+
+    impl Gateway {
+        async fn accept(mut stream: DuplexStream, server_version: u64) -> Gateway {
+            let mut prefix = [0; 4];
+            stream.read_exact(&mut prefix).await.unwrap();
+            assert_eq!(&prefix, b"API\0");
+            read_frame(&mut stream).await.unwrap(); // client versions
+
+            let mut gateway = Gateway { stream, start_api: Vec::new() };
+            gateway.send(&[&server_version.to_string(), "20240102 09:30:00 EST"]).await;
+            gateway.start_api = gateway.recv().await;
+            gateway
+        }
+
+        async fn recv(&mut self) -> Vec<String> {
+            let frame = time::timeout(TIMEOUT, read_frame(&mut self.stream)).await.unwrap().unwrap();
+            protocol::frame_fields(&frame)
+        }
+
+        async fn send(&mut self, fields: &[&str]) {
+            let frame: Vec<u8> = fields.iter().flat_map(|f| f.bytes().chain(Some(0))).collect();
+            self.stream.write_u32(frame.len() as u32).await.unwrap();
+            self.stream.write_all(&frame).await.unwrap();
+        }
+    }
+
+    async fn connect() -> (AsyncClient, Gateway) {
+        let (client, server) = tokio::io::duplex(4096);
+        let gateway = tokio::spawn(Gateway::accept(server, 151));
+        let client = AsyncClient::connect_stream(client, &ConnectOptions::new()).await.unwrap();
+
+        (client.timeout(TIMEOUT), gateway.await.unwrap())
+    }
+
+    #[test]
+    fn handshake() {
+        runtime().block_on(async {
+            let (client, gateway) = connect().await;
+
+            assert_eq!(client.server_version, 151);
+            assert_eq!(gateway.start_api, ["71", "2", "0", ""]);
+        });
+    }
+
+    #[test]
+    fn contract_details_until_end() {
+        runtime().block_on(async {
+            let (mut client, mut gateway) = connect().await;
+
+            let gateway = tokio::spawn(async move {
+                let request = gateway.recv().await;
+                gateway.send(&["49", "1", "1704205800"]).await;
+                gateway.send(&["52", "1", &request[2]]).await;
+                (gateway, request)
+            });
+
+            assert!(client.contract_details(&Default::default()).await.unwrap().is_empty());
+
+            let (_gateway, request) = gateway.await.unwrap();
+            assert_eq!(request[..2], ["9", "8"]);
+            assert!(matches!(client.messages.next().await, Some(Message::CurrentTime { time: 1704205800, .. })));
+        });
+    }
+
+    #[test]
+    fn pending_requests_fail_when_disconnected() {
+        runtime().block_on(async {
+            let (mut client, mut gateway) = connect().await;
+
+            tokio::spawn(async move {
+                gateway.recv().await;
+            });
+
+            let started = time::Instant::now();
+            assert!(matches!(client.contract_details(&Default::default()).await, Err(RequestError::Disconnected)));
+            assert!(started.elapsed() < TIMEOUT);
+
+            assert!(matches!(client.messages.next().await, Some(Message::Disconnected)));
+            assert!(client.messages.next().await.is_none());
+            assert!(matches!(client.open_orders().await, Err(RequestError::Disconnected)));
+        });
+    }
+
+    #[test]
+    fn dropped_market_data_is_cancelled() {
+        runtime().block_on(async {
+            let (client, mut gateway) = connect().await;
+
+            let mut ticks = client.market_data(&Default::default(), "", false);
+            let request_id = ticks.request_id().to_string();
+            assert_eq!(gateway.recv().await[..3], ["1", "11", request_id.as_str()]);
+
+            gateway.send(&["2", "6", &request_id, "0", "100"]).await;
+            assert_eq!(ticks.next().await, Some(Tick::Size { tick_type: 0, size: 100 }));
+
+            drop(ticks);
+            assert_eq!(gateway.recv().await, ["2", "2", request_id.as_str()]);
+        });
+    }
+
+    #[test]
+    fn snapshots_are_not_cancelled() {
+        runtime().block_on(async {
+            let (client, mut gateway) = connect().await;
+
+            let mut ticks = client.market_data(&Default::default(), "", true);
+            let request_id = ticks.request_id().to_string();
+            gateway.recv().await;
+
+            gateway.send(&["57", "1", &request_id]).await;
+            assert!(ticks.next().await.is_none());
+
+            drop(ticks);
+            client.send(Request::ReqCurrentTime);
+            assert_eq!(gateway.recv().await, ["49", "1"]);
+        });
+    }
+}
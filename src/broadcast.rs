@@ -0,0 +1,383 @@
+//! Fan-out of incoming messages to any number of subscribers.
+//!
+//! Iterating `Socket::rx` from several places hands each message to only one
+//! of them. A `Broadcast` takes over the receiver instead and sends a clone
+//! of each `Envelope` to every `Subscriber` whose `Filter` matches it.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, Receiver, SendTimeoutError, Sender, TrySendError};
+use log::{debug, warn};
+use smart_default::SmartDefault;
+
+use crate::ib::Message;
+use crate::socket::{Envelope, Socket};
+
+/// Selects messages for a subscriber. Each criterion that is set must match,
+/// by any of its values. Messages without the field, e.g. without an
+/// account, don't match a criterion on it.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    kinds: Vec<&'static str>,
+    request_ids: Vec<i32>,
+    order_ids: Vec<i32>,
+    accounts: Vec<String>,
+    conids: Vec<i32>,
+}
+
+impl Filter {
+    /// Matches every message
+    pub fn all() -> Filter {
+        Filter::default()
+    }
+
+    /// Messages of kind `kind`, see `Message::kind`
+    pub fn kind(mut self, kind: &'static str) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Messages for `request_id`, see `Message::request_id`
+    pub fn request_id(mut self, request_id: i32) -> Self {
+        self.request_ids.push(request_id);
+        self
+    }
+
+    /// Messages about the order `order_id`, see `Message::order_id`
+    pub fn order_id(mut self, order_id: i32) -> Self {
+        self.order_ids.push(order_id);
+        self
+    }
+
+    /// Messages about `account`, see `Message::account`
+    pub fn account<S: Into<String>>(mut self, account: S) -> Self {
+        self.accounts.push(account.into());
+        self
+    }
+
+    /// Messages about the contract `conid`, see `Message::conid`
+    pub fn conid(mut self, conid: i32) -> Self {
+        self.conids.push(conid);
+        self
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&msg.kind())) &&
+        (self.request_ids.is_empty() || matches!(msg.request_id(), Some(id) if self.request_ids.contains(&id))) &&
+        (self.order_ids.is_empty() || matches!(msg.order_id(), Some(id) if self.order_ids.contains(&id))) &&
+        (self.accounts.is_empty() || matches!(msg.account(), Some(a) if self.accounts.iter().any(|b| a == b))) &&
+        (self.conids.is_empty() || matches!(msg.conid(), Some(id) if self.conids.contains(&id)))
+    }
+}
+
+/// What to do with a message for a subscriber whose queue is full
+#[derive(Clone, Copy, Debug, Eq, PartialEq, SmartDefault)]
+pub enum Overflow {
+    /// Discard the new message
+    #[default]
+    DropNewest,
+    /// Discard the oldest queued message to make room
+    DropOldest,
+    /// Wait for room, which delays all other subscribers too
+    Block,
+    /// Unsubscribe, so the subscriber's receiver disconnects once drained
+    Disconnect,
+}
+
+/// Options of `Broadcast::subscribe`
+#[derive(Clone, Debug, SmartDefault)]
+pub struct SubscribeOptions {
+    pub filter: Filter,
+    /// Maximum number of queued messages
+    #[default(10_000)]
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl SubscribeOptions {
+    pub fn new(filter: Filter) -> SubscribeOptions {
+        SubscribeOptions { filter, .. Default::default() }
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+type Entries = Arc<Mutex<Vec<Arc<Entry>>>>;
+
+/// The sender is only referenced from `Entries` and while delivering, so
+/// removing the entry disconnects the subscriber.
+struct Entry {
+    id: usize,
+    options: SubscribeOptions,
+    tx: Sender<Envelope>,
+    /// Kept to discard the oldest message on `Overflow::DropOldest`
+    rx: Receiver<Envelope>,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    active: AtomicBool,
+    dropped: AtomicU64,
+}
+
+/// Reads messages on a thread and sends them to all matching subscribers.
+/// Subscribers are disconnected when the messages end, e.g. when the
+/// `Socket` is closed.
+pub struct Broadcast {
+    entries: Entries,
+    next_id: AtomicUsize,
+}
+
+impl Broadcast {
+    /// Takes over the messages of `socket`, which nothing else should read
+    /// from afterwards. Not to be combined with a `Client` on the same socket.
+    pub fn new(socket: &Socket) -> Broadcast {
+        Broadcast::from_receiver(socket.rx.clone())
+    }
+
+    pub fn from_receiver(messages: Receiver<Envelope>) -> Broadcast {
+        let entries = Entries::default();
+
+        let fan_out = entries.clone();
+        thread::spawn(move|| run(messages, fan_out));
+
+        Broadcast { entries, next_id: AtomicUsize::new(0) }
+    }
+
+    /// Messages arriving from now on that match `options.filter`
+    pub fn subscribe(&self, options: SubscribeOptions) -> Subscriber {
+        let (tx, rx) = bounded(options.capacity);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(State { active: AtomicBool::new(true), .. Default::default() });
+
+        self.entries.lock().unwrap().push(Arc::new(Entry {
+            id,
+            options,
+            tx,
+            rx: rx.clone(),
+            state: state.clone(),
+        }));
+
+        Subscriber { rx, id, state, entries: self.entries.clone() }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+/// Receiving end of `Broadcast::subscribe`. Dropping it unsubscribes.
+pub struct Subscriber {
+    pub rx: Receiver<Envelope>,
+    id: usize,
+    state: Arc<State>,
+    entries: Entries,
+}
+
+impl Subscriber {
+    /// Messages discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    /// False once unsubscribed for `Overflow::Disconnect` or because the
+    /// messages ended
+    pub fn is_active(&self) -> bool {
+        self.state.active.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.state.active.store(false, Ordering::Relaxed);
+        unsubscribe(&self.entries, self.id);
+    }
+}
+
+impl Iterator for &Subscriber {
+    type Item = Envelope;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+fn unsubscribe(entries: &Entries, id: usize) {
+    entries.lock().unwrap().retain(|entry| entry.id != id);
+}
+
+fn run(messages: Receiver<Envelope>, entries: Entries) {
+    for msg in messages {
+        // Not holding the lock while sending, so `Overflow::Block` doesn't
+        // block subscribing and unsubscribing
+        let matching: Vec<Arc<Entry>> = entries.lock().unwrap().iter()
+            .filter(|entry| entry.options.filter.matches(&msg))
+            .cloned()
+            .collect();
+
+        for entry in matching {
+            if !deliver(&entry, msg.clone()) {
+                warn!("Subscriber {} overflowed, unsubscribing", entry.id);
+                entry.state.active.store(false, Ordering::Relaxed);
+                unsubscribe(&entries, entry.id);
+            }
+        }
+    }
+
+    debug!("Messages ended, disconnecting subscribers");
+    for entry in entries.lock().unwrap().drain(..) {
+        entry.state.active.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Returns false if the subscriber is to be disconnected
+fn deliver(entry: &Entry, mut msg: Envelope) -> bool {
+    loop {
+        match entry.options.overflow {
+            Overflow::Block => match entry.tx.send_timeout(msg, Duration::from_millis(100)) {
+                Ok(()) => return true,
+                // Gave up waiting if unsubscribed meanwhile
+                Err(SendTimeoutError::Timeout(m)) if entry.state.active.load(Ordering::Relaxed) => msg = m,
+                Err(_) => return true,
+            },
+            overflow => match entry.tx.try_send(msg) {
+                Ok(()) => return true,
+                Err(TrySendError::Full(m)) => {
+                    entry.state.dropped.fetch_add(1, Ordering::Relaxed);
+
+                    match overflow {
+                        Overflow::DropOldest => {
+                            let _ = entry.rx.try_recv();
+                            msg = m;
+                        },
+                        Overflow::Disconnect => return false,
+                        _ => return true,
+                    }
+                },
+                Err(TrySendError::Disconnected(_)) => return true,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use crossbeam_channel::{unbounded, RecvTimeoutError, Sender};
+
+    use crate::ib::{Contract, Message};
+    use crate::socket::Envelope;
+    use super::{Broadcast, Filter, Overflow, SubscribeOptions, Subscriber};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn broadcast() -> (Broadcast, Sender<Envelope>) {
+        let (tx, rx) = unbounded();
+        (Broadcast::from_receiver(rx), tx)
+    }
+
+    fn send(tx: &Sender<Envelope>, seq: u64, message: Message) {
+        tx.send(Envelope { seq, received: Instant::now(), received_at: SystemTime::now(), frame_len: 0, message }).unwrap();
+    }
+
+    fn subscribe(broadcast: &Broadcast, capacity: usize, overflow: Overflow) -> Subscriber {
+        broadcast.subscribe(SubscribeOptions::new(Filter::all()).capacity(capacity).overflow(overflow))
+    }
+
+    /// Sends `count` messages and ends them
+    fn send_and_end(tx: Sender<Envelope>, count: u64) {
+        for seq in 0..count {
+            send(&tx, seq, Message::CurrentTime { version: 1, time: seq as i64 });
+        }
+    }
+
+    /// Sequence numbers until the subscriber is disconnected
+    fn received(subscriber: &Subscriber) -> Vec<u64> {
+        subscriber.rx.iter().map(|envelope| envelope.seq).collect()
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (broadcast, tx) = broadcast();
+        let subscriber = subscribe(&broadcast, 2, Overflow::DropNewest);
+
+        send_and_end(tx, 4);
+
+        assert_eq!(received(&subscriber), [0, 1]);
+        assert_eq!(subscriber.dropped(), 2);
+        assert!(!subscriber.is_active());
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (broadcast, tx) = broadcast();
+        let subscriber = subscribe(&broadcast, 2, Overflow::DropOldest);
+
+        send_and_end(tx, 4);
+
+        assert_eq!(received(&subscriber), [2, 3]);
+        assert_eq!(subscriber.dropped(), 2);
+    }
+
+    #[test]
+    fn disconnect_on_overflow() {
+        let (broadcast, tx) = broadcast();
+        let overflowing = subscribe(&broadcast, 1, Overflow::Disconnect);
+        let other = subscribe(&broadcast, 10, Overflow::DropNewest);
+
+        for seq in 0..3 {
+            send(&tx, seq, Message::CurrentTime { version: 1, time: 0 });
+        }
+
+        // Disconnected while the messages continue
+        assert_eq!(overflowing.rx.recv_timeout(TIMEOUT).map(|envelope| envelope.seq), Ok(0));
+        assert_eq!(overflowing.rx.recv_timeout(TIMEOUT).unwrap_err(), RecvTimeoutError::Disconnected);
+        assert_eq!(overflowing.dropped(), 1);
+        assert!(!overflowing.is_active());
+
+        assert_eq!(other.rx.recv_timeout(TIMEOUT).map(|envelope| envelope.seq), Ok(0));
+        assert!(other.is_active());
+        assert_eq!(broadcast.subscribers(), 1);
+    }
+
+    #[test]
+    fn filters() {
+        let (broadcast, tx) = broadcast();
+        let filtered = |filter: Filter| broadcast.subscribe(SubscribeOptions::new(filter));
+        let account = filtered(Filter::all().account("DU1"));
+        let conid = filtered(Filter::all().conid(2));
+        let request_id = filtered(Filter::all().request_id(5).request_id(6));
+        let both = filtered(Filter::all().account("DU1").conid(2));
+
+        let position = |account: &str, conid| Message::PositionData {
+            version: 3,
+            account: account.into(),
+            contract: Contract { conid, ..Default::default() },
+            position: 1.0,
+            avg_cost: 1.0,
+        };
+        send(&tx, 0, position("DU1", 1));
+        send(&tx, 1, position("DU2", 2));
+        send(&tx, 2, Message::ContractDataEnd { version: 1, req_id: 5 });
+        send(&tx, 3, Message::CurrentTime { version: 1, time: 0 });
+        drop(tx);
+
+        assert_eq!(received(&account), [0]);
+        assert_eq!(received(&conid), [1]);
+        assert_eq!(received(&request_id), [2]);
+        assert!(received(&both).is_empty());
+    }
+}
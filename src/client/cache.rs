@@ -0,0 +1,178 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::ib::{Bar, BarSize, Contract, ContractDetails, Right, WhatToShow};
+use crate::ib::contract::{ComboLeg, DeltaNeutralContract};
+
+/// Identifies a series of cached bars, one file per day
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BarsKey {
+    pub conid: i32,
+    pub bar_size: BarSize,
+    pub what_to_show: WhatToShow,
+    pub use_rth: bool,
+}
+
+/// Local cache of historical bars and contract details, stored as JSON files
+/// under a directory:
+///
+/// - `bars/<conid>/<bar size>-<what to show>-<rth|all>/<yyyyMMdd>.json`:
+///   all bars starting on that (UTC) day, `[]` for a day without bars
+/// - `contracts/<conid>.json`: `ContractDetails`, including the `Contract`
+///   fields that are not sent in requests
+///
+/// A day is only stored once it is over, so a cached day is always complete.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn contract_details(&self, conid: i32) -> Option<ContractDetails> {
+        read(&self.contract_path(conid))
+    }
+
+    pub fn store_contract_details(&self, details: &ContractDetails) -> io::Result<()> {
+        let mut value = serde_json::to_value(details)?;
+        value["contract"] = ContractDef::serialize(&details.contract, serde_json::value::Serializer)?;
+
+        write(&self.contract_path(details.contract.conid), &value)
+    }
+
+    pub fn bars(&self, key: &BarsKey, date: NaiveDate) -> Option<Vec<Bar>> {
+        read(&self.bars_path(key, date))
+    }
+
+    pub fn store_bars(&self, key: &BarsKey, date: NaiveDate, bars: &[Bar]) -> io::Result<()> {
+        write(&self.bars_path(key, date), &bars)
+    }
+
+    fn contract_path(&self, conid: i32) -> PathBuf {
+        self.dir.join("contracts").join(format!("{}.json", conid))
+    }
+
+    fn bars_path(&self, key: &BarsKey, date: NaiveDate) -> PathBuf {
+        let series = format!("{}-{:?}-{}",
+            format!("{:?}", key.bar_size).trim_start_matches('_'),
+            key.what_to_show,
+            if key.use_rth { "rth" } else { "all" });
+
+        self.dir.join("bars")
+            .join(key.conid.to_string())
+            .join(series)
+            .join(format!("{}.json", date.format("%Y%m%d")))
+    }
+}
+
+/// Serializes every field of `Contract`, which itself skips the fields not
+/// sent in requests. Deserializing `Contract` reads them all.
+#[derive(Serialize)]
+#[serde(remote="Contract")]
+struct ContractDef {
+    conid: i32,
+    symbol: String,
+    sec_type: String,
+    last_trade_date_or_contract_month: String,
+    strike: f64,
+    right: Right,
+    multiplier: String,
+    exchange: String,
+    primary_exch: String,
+    currency: String,
+    local_symbol: String,
+    trading_class: String,
+    sec_id_type: String,
+    sec_id: String,
+    delta_neutral_contract: Option<DeltaNeutralContract>,
+    include_expired: bool,
+    combo_legs_descrip: String,
+    combo_legs: Vec<ComboLeg>,
+}
+
+/// Missing and unreadable files are both a cache miss
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            warn!("Cannot read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    match serde_json::from_slice(&data) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("Ignoring corrupt cache file {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Writes to a temporary file first, so readers never see a partial file.
+/// The temporary file is unique to the process and the write, as processes
+/// and clients may share a cache.
+fn write<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp = path.with_extension(format!("{}.{}.tmp", process::id(), WRITES.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&tmp, serde_json::to_vec(value)?)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::ib::{Contract, ContractDetails};
+    use crate::ib::contract::{ComboLeg, DeltaNeutralContract};
+
+    use super::Cache;
+
+    #[test]
+    fn contract_details_keep_all_contract_fields() {
+        let cache = Cache::new(env::temp_dir().join(format!("ibapi-cache-{}", std::process::id())));
+        let details = ContractDetails {
+            contract: Contract {
+                conid: 28812380,
+                symbol: "ES".into(),
+                sec_type: "BAG".into(),
+                sec_id_type: "ISIN".into(),
+                sec_id: "US0378331005".into(),
+                delta_neutral_contract: Some(DeltaNeutralContract { conid: 1, delta: 0.5, price: 10.0 }),
+                include_expired: true,
+                combo_legs_descrip: "1|1".into(),
+                combo_legs: vec![ComboLeg { con_id: 1, ratio: 1, ..ComboLeg::default() }],
+                ..Contract::default()
+            },
+            market_name: "ES".into(),
+            ..ContractDetails::default()
+        };
+
+        cache.store_contract_details(&details).unwrap();
+        let cached = cache.contract_details(28812380);
+        fs::remove_dir_all(cache.dir()).unwrap();
+
+        assert_eq!(cached, Some(details));
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use log::{debug, warn};
+
+use crate::ib::{Bar, BarSize, Contract, ErrorCode, WhatToShow};
+
+use super::{BarsKey, Cache, Client, HistoricalQuery, RequestError};
+
+/// Historical bars between two points in time, downloaded in as many
+/// requests as needed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoricalRange {
+    pub contract: Contract,
+    pub bar_size: BarSize,
+    pub what_to_show: WhatToShow,
+    pub use_rth: bool,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl HistoricalRange {
+    /// Longest request allowed for the bar size, ending at `end`
+    pub fn chunk(&self, end: DateTime<Utc>) -> HistoricalQuery {
+        HistoricalQuery {
+            contract: self.contract.clone(),
+            end_date_time: end.format("%Y%m%d %H:%M:%S GMT").to_string(),
+            duration: self.bar_size.max_duration(),
+            bar_size: self.bar_size,
+            what_to_show: self.what_to_show,
+            use_rth: self.use_rth,
+            format_date: 2,
+        }
+    }
+}
+
+fn is_no_data(err: &RequestError) -> bool {
+    match err {
+        RequestError::Error { code: ErrorCode::HistoricalDataServiceError, message } => message.contains("returned no data"),
+        _ => false,
+    }
+}
+
+impl Client {
+    /// Downloads all bars starting in `[range.start, range.end)`, sorted by time.
+    ///
+    /// The range is split into requests of `BarSize::max_duration`, issued
+    /// from `range.end` backwards, each ending at the first bar of the one
+    /// before. Bars received twice are kept once. Each request goes through
+    /// `historical_data`, so long ranges wait for the pacing rules.
+    ///
+    /// With a `Cache`, only days not cached yet are requested, as whole days.
+    /// The contract needs a conid to be cached.
+    pub fn historical_range(&self, range: &HistoricalRange) -> Result<Vec<Bar>, RequestError> {
+        match &self.cache {
+            Some(cache) if range.contract.conid != 0 => self.cached_range(cache, range),
+            _ => self.download_range(range),
+        }
+    }
+
+    fn download_range(&self, range: &HistoricalRange) -> Result<Vec<Bar>, RequestError> {
+        let mut bars = BTreeMap::new();
+        let mut end = range.end;
+
+        while end > range.start {
+            let query = range.chunk(end);
+
+            let chunk = match self.historical_data(&query) {
+                Ok(chunk) => chunk,
+                Err(ref err) if is_no_data(err) => Vec::new(),
+                Err(err) => return Err(err),
+            };
+
+            debug!("{} bars ending {}", chunk.len(), end);
+
+            let mut first = end;
+            for bar in chunk {
+                let time = match bar.timestamp() {
+                    Some(time) => time,
+                    None => continue,
+                };
+
+                first = first.min(time);
+                if time >= range.start && time < range.end {
+                    bars.insert(time, bar);
+                }
+            }
+
+            // Nothing before `end`, e.g. over a holiday
+            end = if first < end { first } else { end - query.duration.max_span() };
+        }
+
+        Ok(bars.into_values().collect())
+    }
+
+    fn cached_range(&self, cache: &Cache, range: &HistoricalRange) -> Result<Vec<Bar>, RequestError> {
+        let key = BarsKey {
+            conid: range.contract.conid,
+            bar_size: range.bar_size,
+            what_to_show: range.what_to_show,
+            use_rth: range.use_rth,
+        };
+        let today = today();
+
+        let mut bars = Vec::new();
+        let mut gap: Option<NaiveDate> = None;
+        let mut date = range.start.date_naive();
+        let last = (range.end - Duration::nanoseconds(1)).date_naive();
+
+        while date <= last {
+            let cached = if date < today { cache.bars(&key, date) } else { None };
+
+            match cached {
+                Some(day) => {
+                    if let Some(from) = gap.take() {
+                        bars.extend(self.fetch_days(cache, &key, range, from, date)?);
+                    }
+                    bars.extend(day.into_iter().filter(|bar| in_range(bar, range)));
+                },
+                None => {
+                    gap.get_or_insert(date);
+                },
+            }
+
+            date = date.succ_opt().expect("date in range");
+        }
+
+        if let Some(from) = gap {
+            bars.extend(self.fetch_days(cache, &key, range, from, date)?);
+        }
+
+        Ok(bars)
+    }
+
+    /// Downloads the days `[from, to)` and caches the ones that are over
+    fn fetch_days(&self, cache: &Cache, key: &BarsKey, range: &HistoricalRange, from: NaiveDate, to: NaiveDate) -> Result<Vec<Bar>, RequestError> {
+        let days = HistoricalRange {
+            start: midnight(from),
+            end: midnight(to),
+            .. range.clone()
+        };
+        let fetched = self.download_range(&days)?;
+
+        let today = today();
+        let mut date = from;
+        while date < to && date < today {
+            let day: Vec<Bar> = fetched.iter()
+                .filter(|bar| bar.timestamp().map(|t| t.date_naive()) == Some(date))
+                .cloned()
+                .collect();
+
+            if let Err(err) = cache.store_bars(key, date, &day) {
+                warn!("Cannot cache bars of {}: {}", date, err);
+            }
+            date = date.succ_opt().expect("date in range");
+        }
+
+        Ok(fetched.into_iter().filter(|bar| in_range(bar, range)).collect())
+    }
+}
+
+fn today() -> NaiveDate {
+    DateTime::<Utc>::from(SystemTime::now()).date_naive()
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("valid time"))
+}
+
+fn in_range(bar: &Bar, range: &HistoricalRange) -> bool {
+    matches!(bar.timestamp(), Some(t) if t >= range.start && t < range.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use crate::client::PacingLimits;
+    use crate::ib::{Bar, BarSize, Contract, WhatToShow};
+    use crate::socket::pipe::{self, FakeGateway, PipeEnd};
+    use crate::socket::ConnectOptions;
+    use super::{midnight, today, BarsKey, Cache, Client, HistoricalRange};
+
+    fn range(start: (i32, u32, u32), end: (i32, u32, u32)) -> HistoricalRange {
+        let date = |(y, m, d)| Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(0, 0, 0).unwrap());
+
+        HistoricalRange {
+            contract: Contract { symbol: "SPY".into(), ..Default::default() },
+            bar_size: BarSize::_1_day,
+            what_to_show: WhatToShow::TRADES,
+            use_rth: true,
+            start: date(start),
+            end: date(end),
+        }
+    }
+
+    /// Answers historical data requests in turn with bars at `days`, or with
+    /// "no data" for `None`. Returns the end of each request.
+    fn serve<S: AsRef<str> + Send + 'static>(mut gateway: FakeGateway<PipeEnd>, responses: Vec<Option<Vec<S>>>) -> JoinHandle<Vec<String>> {
+        thread::spawn(move|| {
+            let mut ends = Vec::new();
+
+            for days in responses {
+                let request = gateway.recv();
+                assert_eq!(request[0], "20");
+                let id = request[1].as_str();
+                ends.push(request.iter().find(|f| f.ends_with(" GMT")).unwrap().clone());
+
+                match days {
+                    Some(days) => {
+                        let count = days.len().to_string();
+                        let mut frame = vec!["17", id, "", "", &count];
+                        for day in &days {
+                            frame.extend(&[day.as_ref(), "1", "1", "1", "1", "100", "1", "10"]);
+                        }
+                        gateway.send(&frame);
+                    },
+                    None => gateway.send(&["4", "2", id, "162", "Historical Market Data Service error message:HMDS query returned no data: SPY@ARCA Trades"]),
+                }
+            }
+            ends
+        })
+    }
+
+    fn days(bars: &[Bar]) -> Vec<&str> {
+        bars.iter().map(|bar| bar.time.as_str()).collect()
+    }
+
+    #[test]
+    fn downloads_backwards_in_chunks() {
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket);
+
+        let gateway = serve(gateway, vec![
+            Some(vec!["20230601", "20230901", "20240110"]),
+            // Overlaps the first chunk
+            Some(vec!["20230301", "20230601"]),
+            None,
+            Some(vec!["20211231", "20220201"]),
+        ]);
+
+        let bars = client.historical_range(&range((2022, 1, 1), (2024, 1, 10))).unwrap();
+
+        assert_eq!(days(&bars), ["20220201", "20230301", "20230601", "20230901"]);
+        assert_eq!(gateway.join().unwrap(), [
+            "20240110 00:00:00 GMT",
+            "20230601 00:00:00 GMT",
+            "20230301 00:00:00 GMT",
+            // A year (366 days) before the chunk without data
+            "20220228 00:00:00 GMT",
+        ]);
+    }
+
+    #[test]
+    fn stops_after_no_data_before_start() {
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket);
+
+        let gateway = serve::<&str>(gateway, vec![None]);
+
+        assert!(client.historical_range(&range((2023, 6, 1), (2024, 1, 1))).unwrap().is_empty());
+        assert_eq!(gateway.join().unwrap(), ["20240101 00:00:00 GMT"]);
+    }
+
+    fn cache(name: &str) -> Cache {
+        Cache::new(env::temp_dir().join(format!("ibapi-cache-{}-{}", std::process::id(), name)))
+    }
+
+    fn key(range: &HistoricalRange) -> BarsKey {
+        BarsKey { conid: range.contract.conid, bar_size: range.bar_size, what_to_show: range.what_to_show, use_rth: range.use_rth }
+    }
+
+    fn bar(day: &str) -> Bar {
+        Bar { time: day.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn requests_only_days_not_cached() {
+        let cache = cache("gaps");
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket).cache(cache.clone());
+
+        let range = HistoricalRange {
+            contract: Contract { conid: 756733, ..Default::default() },
+            .. range((2024, 1, 1), (2024, 1, 5))
+        };
+        let date = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        cache.store_bars(&key(&range), date(1), &[bar("20240101")]).unwrap();
+        cache.store_bars(&key(&range), date(3), &[bar("20240103")]).unwrap();
+
+        let gateway = serve(gateway, vec![Some(vec!["20240102"]), Some(vec!["20240104"])]);
+        let bars = client.historical_range(&range).unwrap();
+        let stored = cache.bars(&key(&range), date(4));
+        fs::remove_dir_all(cache.dir()).unwrap();
+
+        assert_eq!(days(&bars), ["20240101", "20240102", "20240103", "20240104"]);
+        assert_eq!(gateway.join().unwrap(), ["20240103 00:00:00 GMT", "20240105 00:00:00 GMT"]);
+        assert_eq!(stored.as_deref().map(days), Some(vec!["20240104"]));
+    }
+
+    #[test]
+    fn today_is_never_stored() {
+        let cache = cache("today");
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket)
+            .cache(cache.clone())
+            .historical_limits(PacingLimits { identical_interval: Duration::default(), ..Default::default() });
+
+        let today = today();
+        let yesterday = today.pred_opt().unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+        let range = HistoricalRange {
+            contract: Contract { conid: 756733, ..Default::default() },
+            start: midnight(yesterday),
+            end: midnight(tomorrow),
+            .. range((2024, 1, 1), (2024, 1, 2))
+        };
+        let day = |date: NaiveDate| date.format("%Y%m%d").to_string();
+
+        let gateway = serve(gateway, vec![
+            Some(vec![day(yesterday), day(today)]),
+            // Only today is requested again
+            Some(vec![day(today)]),
+        ]);
+        let first = client.historical_range(&range).unwrap();
+        let second = client.historical_range(&range).unwrap();
+        let stored = (cache.bars(&key(&range), yesterday), cache.bars(&key(&range), today));
+        fs::remove_dir_all(cache.dir()).unwrap();
+
+        assert_eq!(first.len(), 2);
+        assert_eq!(second.len(), 2);
+        let end = format!("{} 00:00:00 GMT", day(tomorrow));
+        assert_eq!(gateway.join().unwrap(), [end.clone(), end]);
+        assert!(stored.0.is_some());
+        assert!(stored.1.is_none());
+    }
+}
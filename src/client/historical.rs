@@ -0,0 +1,355 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::RecvTimeoutError;
+use log::{info, warn};
+use smart_default::SmartDefault;
+
+use crate::ib::{Bar, BarSize, Contract, ErrorClass, ErrorCode, HistoricalDuration, Message, Request, WhatToShow};
+
+use super::{Client, RequestError};
+
+/// A `ReqHistoricalData` without the request id, so it can be scheduled and
+/// compared with earlier requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoricalQuery {
+    pub contract: Contract,
+    /// yyyyMMdd HH:mm:ss with optional time zone, empty for now
+    pub end_date_time: String,
+    pub duration: HistoricalDuration,
+    pub bar_size: BarSize,
+    pub what_to_show: WhatToShow,
+    pub use_rth: bool,
+    /// 1 for yyyyMMdd HH:mm:ss, 2 for seconds since the Unix epoch
+    pub format_date: i32,
+}
+
+impl HistoricalQuery {
+    pub fn request(&self, ticker_id: i32) -> Request {
+        Request::ReqHistoricalData {
+            ticker_id,
+            contract: self.contract.clone(),
+            end_date_time: self.end_date_time.clone(),
+            bar_size: self.bar_size,
+            duration: self.duration,
+            use_rth: self.use_rth,
+            what_to_show: self.what_to_show,
+            format_date: self.format_date,
+            keep_up_to_date: false,
+            chart_options: Vec::new(),
+        }
+    }
+}
+
+/// Historical data pacing rules, defaults as documented by IB
+#[derive(Clone, Debug, SmartDefault)]
+pub struct PacingLimits {
+    /// At most `max_requests` per `window`, 0 for no limit
+    #[default(60)]
+    pub max_requests: usize,
+    #[default(Duration::from_secs(600))]
+    pub window: Duration,
+    /// Minimum time between identical requests
+    #[default(Duration::from_secs(15))]
+    pub identical_interval: Duration,
+    /// Wait after the first pacing violation, doubling with each further one
+    #[default(Duration::from_secs(15))]
+    pub min_backoff: Duration,
+    #[default(Duration::from_secs(600))]
+    pub max_backoff: Duration,
+    /// Retries of a request after pacing violations before giving up with
+    /// the pacing error
+    #[default(5)]
+    pub max_retries: u32,
+    /// Maximum time to wait for the bars of a single request
+    #[default(Duration::from_secs(120))]
+    pub timeout: Duration,
+}
+
+/// Schedules historical data requests in the order they are made, so that
+/// none of them violates `PacingLimits`.
+#[derive(Debug, Default)]
+pub(crate) struct HistoricalPacing {
+    pub(crate) limits: PacingLimits,
+    state: Mutex<PacingState>,
+}
+
+#[derive(Debug, Default)]
+struct PacingState {
+    /// Send times of requests in the current window, including reserved ones
+    sent: VecDeque<Instant>,
+    recent: Vec<(Instant, HistoricalQuery)>,
+    blocked_until: Option<Instant>,
+}
+
+impl HistoricalPacing {
+    pub(crate) fn new(limits: PacingLimits) -> HistoricalPacing {
+        HistoricalPacing { limits, state: Default::default() }
+    }
+
+    /// Time until `query` could be sent if it were requested now
+    pub(crate) fn expected_wait(&self, query: &HistoricalQuery) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.prune(&self.limits, now);
+        state.slot(&self.limits, query, now).duration_since(now)
+    }
+
+    /// Reserves the next slot for `query` and returns the time to wait for it
+    pub(crate) fn reserve(&self, query: &HistoricalQuery) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.prune(&self.limits, now);
+
+        let slot = state.slot(&self.limits, query, now);
+        state.sent.push_back(slot);
+        state.recent.push((slot, query.clone()));
+
+        slot.duration_since(now)
+    }
+
+    pub(crate) fn violated(&self, backoff: Duration) {
+        let until = Instant::now() + backoff;
+        let mut state = self.state.lock().unwrap();
+        state.blocked_until = Some(state.blocked_until.map_or(until, |t| t.max(until)));
+    }
+}
+
+impl PacingState {
+    fn prune(&mut self, limits: &PacingLimits, now: Instant) {
+        while matches!(self.sent.front(), Some(&t) if t + limits.window <= now) {
+            self.sent.pop_front();
+        }
+        self.recent.retain(|&(t, _)| t + limits.identical_interval > now);
+    }
+
+    /// Earliest time `query` can be sent. Slots are handed out in order, so a
+    /// request never overtakes one made before it.
+    fn slot(&self, limits: &PacingLimits, query: &HistoricalQuery, now: Instant) -> Instant {
+        let mut slot = now;
+
+        if let Some(&last) = self.sent.back() {
+            slot = slot.max(last);
+        }
+        if let Some(blocked_until) = self.blocked_until {
+            slot = slot.max(blocked_until);
+        }
+        if limits.max_requests > 0 && self.sent.len() >= limits.max_requests {
+            slot = slot.max(self.sent[self.sent.len() - limits.max_requests] + limits.window);
+        }
+        for (t, _) in self.recent.iter().filter(|(_, q)| q == query) {
+            slot = slot.max(*t + limits.identical_interval);
+        }
+
+        slot
+    }
+}
+
+/// What to do after a response to a historical data request
+pub(crate) enum Step {
+    /// Keep waiting for the bars
+    Wait,
+    /// Rejected for a pacing violation, request again
+    Retry,
+    Done(Result<Vec<Bar>, RequestError>),
+}
+
+/// Attempts of a single `historical_data` call, shared by `Client` and
+/// `AsyncClient`. Each attempt waits for its pacing slot, and pacing
+/// violations back off exponentially up to `PacingLimits::max_retries` times.
+pub(crate) struct HistoricalAttempts<'a> {
+    pacing: &'a HistoricalPacing,
+    backoff: Duration,
+    retries: u32,
+}
+
+impl<'a> HistoricalAttempts<'a> {
+    pub(crate) fn new(pacing: &'a HistoricalPacing) -> HistoricalAttempts<'a> {
+        HistoricalAttempts { pacing, backoff: pacing.limits.min_backoff, retries: 0 }
+    }
+
+    /// Reserves a slot for the next attempt and returns the time to wait
+    /// before sending it
+    pub(crate) fn start(&self, query: &HistoricalQuery) -> Duration {
+        let wait = self.pacing.reserve(query);
+        if wait > Duration::default() {
+            info!("Historical data pacing, waiting {:?}", wait);
+        }
+        wait
+    }
+
+    /// Time to wait for the bars of an attempt
+    pub(crate) fn timeout(&self) -> Duration {
+        self.pacing.limits.timeout
+    }
+
+    pub(crate) fn step(&mut self, request_id: i32, msg: Message) -> Step {
+        let limits = &self.pacing.limits;
+
+        match msg {
+            Message::HistoricalData { bars, .. } => Step::Done(Ok(bars)),
+            Message::ErrMsg { error_code, error_msg, .. } if is_pacing_violation(error_code, &error_msg) => {
+                if self.retries == limits.max_retries {
+                    return Step::Done(Err(RequestError::Error { code: error_code, message: error_msg }));
+                }
+                self.retries += 1;
+
+                warn!("Historical data pacing violation, retrying in {:?}", self.backoff);
+                self.pacing.violated(self.backoff);
+                self.backoff = (self.backoff * 2).min(limits.max_backoff);
+                Step::Retry
+            },
+            Message::ErrMsg { error_code, error_msg, .. } if error_code.class() == ErrorClass::RequestError => {
+                Step::Done(Err(RequestError::Error { code: error_code, message: error_msg }))
+            },
+            msg => {
+                info!("Historical data {}: {:?}", request_id, msg);
+                Step::Wait
+            },
+        }
+    }
+}
+
+fn is_pacing_violation(code: ErrorCode, message: &str) -> bool {
+    code == ErrorCode::HistoricalDataServiceError && message.to_lowercase().contains("pacing violation")
+}
+
+impl Client {
+    /// Replaces the default historical data `PacingLimits`
+    pub fn historical_limits(mut self, limits: PacingLimits) -> Self {
+        self.historical = HistoricalPacing::new(limits);
+        self
+    }
+
+    /// Time a `historical_data` call for `query` made now would wait before
+    /// sending the request, not counting pacing violations.
+    pub fn historical_wait(&self, query: &HistoricalQuery) -> Duration {
+        self.historical.expected_wait(query)
+    }
+
+    /// Requests historical bars and blocks until they arrive.
+    ///
+    /// Requests are queued to comply with the pacing rules. Requests rejected
+    /// for a pacing violation anyway (e.g. because of other clients) are
+    /// retried with exponential backoff, up to `PacingLimits::max_retries`
+    /// times.
+    pub fn historical_data(&self, query: &HistoricalQuery) -> Result<Vec<Bar>, RequestError> {
+        let mut attempts = HistoricalAttempts::new(&self.historical);
+
+        loop {
+            thread::sleep(attempts.start(query));
+
+            let responses = self.request(|id| query.request(id));
+            let deadline = Instant::now() + attempts.timeout();
+
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                let step = match responses.rx.recv_timeout(remaining) {
+                    Ok(msg) => attempts.step(responses.request_id, msg),
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.unregister(responses.request_id);
+                        self.send(Request::CancelHistoricalData { ticker_id: responses.request_id });
+                        return Err(RequestError::Timeout);
+                    },
+                    Err(RecvTimeoutError::Disconnected) => return Err(RequestError::Disconnected),
+                };
+
+                match step {
+                    Step::Wait => {},
+                    Step::Retry => break,
+                    Step::Done(result) => return result,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::ib::{BarSize, Contract, DurationUnit, ErrorCode, HistoricalDuration, Message, WhatToShow};
+    use super::{HistoricalAttempts, HistoricalPacing, HistoricalQuery, PacingLimits, RequestError, Step};
+
+    fn query(symbol: &str) -> HistoricalQuery {
+        HistoricalQuery {
+            contract: Contract { symbol: symbol.into(), ..Default::default() },
+            end_date_time: String::new(),
+            duration: HistoricalDuration::new(1, DurationUnit::DAY),
+            bar_size: BarSize::_1_min,
+            what_to_show: WhatToShow::TRADES,
+            use_rth: true,
+            format_date: 2,
+        }
+    }
+
+    fn pacing_violation() -> Message {
+        Message::ErrMsg {
+            version: 2,
+            id: 1,
+            error_code: ErrorCode::HistoricalDataServiceError,
+            error_msg: "Historical Market Data Service error message:API historical data query cancelled: Pacing violation".into(),
+        }
+    }
+
+    #[test]
+    fn slots_keep_to_the_window() {
+        let limits = PacingLimits { max_requests: 2, window: Duration::from_secs(60), identical_interval: Duration::default(), ..Default::default() };
+        let pacing = HistoricalPacing::new(limits);
+        let start = Instant::now();
+
+        assert_eq!(pacing.reserve(&query("A")), Duration::default());
+        assert_eq!(pacing.reserve(&query("B")), Duration::default());
+
+        let wait = pacing.reserve(&query("C"));
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60));
+        // Later requests never overtake it
+        assert!(pacing.expected_wait(&query("D")) + start.elapsed() >= wait);
+    }
+
+    #[test]
+    fn no_window_without_max_requests() {
+        let limits = PacingLimits { max_requests: 0, identical_interval: Duration::default(), ..Default::default() };
+        let pacing = HistoricalPacing::new(limits);
+
+        for symbol in &["A", "B", "C"] {
+            assert_eq!(pacing.reserve(&query(symbol)), Duration::default());
+        }
+    }
+
+    #[test]
+    fn identical_requests_are_spaced() {
+        let limits = PacingLimits { identical_interval: Duration::from_secs(15), ..Default::default() };
+        let pacing = HistoricalPacing::new(limits);
+
+        assert_eq!(pacing.reserve(&query("A")), Duration::default());
+        assert_eq!(pacing.expected_wait(&query("B")), Duration::default());
+
+        let wait = pacing.expected_wait(&query("A"));
+        assert!(wait > Duration::from_secs(14) && wait <= Duration::from_secs(15));
+    }
+
+    #[test]
+    fn retries_pacing_violations_then_gives_up() {
+        let limits = PacingLimits {
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            max_retries: 3,
+            ..Default::default()
+        };
+        let pacing = HistoricalPacing::new(limits);
+        let mut attempts = HistoricalAttempts::new(&pacing);
+
+        for backoff in &[1, 2, 3] {
+            assert!(matches!(attempts.step(1, pacing_violation()), Step::Retry));
+            let wait = pacing.expected_wait(&query("A"));
+            assert!(wait > Duration::from_secs(backoff - 1) && wait <= Duration::from_secs(*backoff));
+        }
+
+        assert!(matches!(attempts.step(1, pacing_violation()),
+            Step::Done(Err(RequestError::Error { code: ErrorCode::HistoricalDataServiceError, .. }))));
+    }
+}
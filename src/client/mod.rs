@@ -0,0 +1,324 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, select, Receiver, Sender, unbounded};
+use log::{debug, warn};
+
+use crate::ib::{ErrorCode, Message, Request};
+use crate::socket::{Envelope, Socket};
+
+pub use self::cache::{BarsKey, Cache};
+pub use self::download::HistoricalRange;
+pub use self::historical::{HistoricalQuery, PacingLimits};
+pub use self::order_ids::OrderIdAllocator;
+pub use self::requests::RequestError;
+pub use self::subscription::{AccountUpdate, DepthUpdate, Subscription, Tick, Update};
+
+use self::historical::HistoricalPacing;
+use self::routes::{is_streaming, Collect, RouteTable};
+
+mod cache;
+mod download;
+pub(crate) mod historical;
+mod order_ids;
+pub(crate) mod requests;
+pub(crate) mod routes;
+pub(crate) mod subscription;
+
+type Routes = Arc<RouteTable<Sender<Message>>>;
+
+/// Shares one `Socket` between independent components.
+///
+/// Requests sent with `Client::request` get their own id and receive only the
+/// messages whose `Message::request_id` matches it. Everything else, e.g.
+/// account updates or connection notices, is passed on to `rx`. An `ErrMsg`
+/// whose code doesn't tell whether it is about a request or an order goes to
+/// the request with that id if there is one, otherwise to `rx`.
+///
+/// Order ids are allocated separately by `order_ids`, which is kept in sync
+/// with `NextValidId`.
+pub struct Client {
+    socket: Socket,
+    routes: Routes,
+    next_request_id: AtomicI32,
+    order_ids: Arc<OrderIdAllocator>,
+    historical: HistoricalPacing,
+    cache: Option<Cache>,
+    timeout: Duration,
+    /// Messages not routed to a request
+    pub rx: Receiver<Message>,
+    /// Never sent on, the dispatcher stops when it disconnects, so dropping
+    /// the `Client` releases the `Socket`
+    _stop: Sender<()>,
+}
+
+/// Responses to a single request. The channel disconnects after the *End
+/// message or a request error, so iterating stops there. Historical data
+/// kept up to date continues after `HistoricalData` until unregistered.
+pub struct Responses {
+    pub request_id: i32,
+    pub rx: Receiver<Message>,
+}
+
+impl Client {
+    pub fn new(socket: Socket) -> Client {
+        let routes = Routes::default();
+        let (tx, rx) = unbounded();
+
+        let order_ids = Arc::new(OrderIdAllocator::default());
+
+        let dispatcher = Dispatcher {
+            routes: routes.clone(),
+            order_ids: order_ids.clone(),
+            requests: socket.tx.clone(),
+            unrouted: tx,
+        };
+        let messages = socket.rx.clone();
+        let (stop, stopped) = bounded(0);
+        thread::spawn(move|| dispatcher.run(messages, stopped));
+
+        Client {
+            socket,
+            routes,
+            next_request_id: AtomicI32::new(1),
+            order_ids,
+            historical: HistoricalPacing::default(),
+            cache: None,
+            timeout: Duration::from_secs(30),
+            rx,
+            _stop: stop,
+        }
+    }
+
+    /// Serves historical data and contract details from `cache` where possible
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn server_version(&self) -> u64 {
+        self.socket.server_version
+    }
+
+    /// Allocates a request id not used by any other request of this client
+    pub fn next_request_id(&self) -> i32 {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Next order id for `PlaceOrder`. If no id is known yet, e.g. right after
+    /// reconnecting, sends `ReqIds` and waits for `NextValidId`.
+    pub fn next_order_id(&self) -> Result<i32, RequestError> {
+        if !self.order_ids.is_seeded() {
+            self.send(Request::ReqIds { num_ids: 1 });
+        }
+
+        self.order_ids.next_timeout(self.timeout).ok_or(RequestError::Timeout)
+    }
+
+    pub fn order_ids(&self) -> &OrderIdAllocator {
+        &self.order_ids
+    }
+
+    /// Allocates a request id, builds the request with it and sends it.
+    /// The route is registered before sending, so no response is missed.
+    ///
+    /// If the connection is gone, `Responses::rx` is disconnected right away.
+    pub fn request<F: FnOnce(i32) -> Request>(&self, build: F) -> Responses {
+        let request_id = self.next_request_id();
+        let request = build(request_id);
+        let rx = self.route(request_id, is_streaming(&request));
+
+        if let Err(err) = self.socket.request(request) {
+            warn!("Request not sent, disconnected: {:?}", err.0);
+            self.unregister(request_id);
+        }
+
+        Responses { request_id, rx }
+    }
+
+    /// Sends a request without routing, responses arrive on `rx`. Requests
+    /// sent after the connection is gone are logged and dropped.
+    pub fn send(&self, request: Request) {
+        if let Err(err) = self.socket.request(request) {
+            warn!("Request not sent, disconnected: {:?}", err.0);
+        }
+    }
+
+    /// Routes messages for `request_id` to the returned channel, e.g. when
+    /// the id was allocated with `next_request_id` and the request sent later.
+    pub fn register(&self, request_id: i32) -> Receiver<Message> {
+        self.route(request_id, false)
+    }
+
+    /// Like `register`, but keeps routing after the *End message until
+    /// unregistered or the request fails, e.g. for `ReqHistoricalData` with
+    /// `keep_up_to_date`.
+    pub fn register_streaming(&self, request_id: i32) -> Receiver<Message> {
+        self.route(request_id, true)
+    }
+
+    fn route(&self, request_id: i32, streaming: bool) -> Receiver<Message> {
+        let (tx, rx) = unbounded();
+        self.routes.insert(request_id, tx, streaming);
+        rx
+    }
+
+    /// Stops routing `request_id`, e.g. after cancelling a subscription.
+    /// Further messages for it are passed on to `rx`.
+    pub fn unregister(&self, request_id: i32) {
+        self.routes.remove(request_id);
+    }
+
+    /// Routes the next messages of `kind` to the returned channel, up to and
+    /// including the *End message. Dropping the receiver stops collecting.
+    pub(crate) fn collect(&self, kind: Collect) -> Receiver<Message> {
+        let (tx, rx) = unbounded();
+        self.routes.collect(kind, tx);
+        rx
+    }
+}
+
+impl Iterator for &Client {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Iterator for &Responses {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+struct Dispatcher {
+    routes: Routes,
+    order_ids: Arc<OrderIdAllocator>,
+    requests: Sender<Request>,
+    unrouted: Sender<Message>,
+}
+
+impl Dispatcher {
+    /// Exits when the `Client` is dropped or the `Socket` stops receiving.
+    /// Routes are cleared when the connection drops, see `RouteTable::clear`.
+    fn run(self, messages: Receiver<Envelope>, stop: Receiver<()>) {
+        loop {
+            let msg = select! {
+                recv(messages) -> msg => match msg {
+                    Ok(envelope) => envelope.into_message(),
+                    Err(_) => {
+                        self.routes.clear();
+                        break;
+                    },
+                },
+                recv(stop) -> _ => break,
+            };
+
+            self.track_order_ids(&msg);
+            if let Message::Disconnected = msg {
+                self.routes.clear();
+            }
+
+            let msg = match self.routes.dispatch(msg) {
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            // Nobody listening for unrouted messages is fine
+            let _ = self.unrouted.send(msg);
+        }
+    }
+
+    fn track_order_ids(&self, msg: &Message) {
+        match msg {
+            Message::NextValidId { order_id, .. } => self.order_ids.seed(*order_id),
+            Message::Disconnected => self.order_ids.invalidate(),
+            Message::Reconnected { .. } |
+            Message::ErrMsg { error_code: ErrorCode::DuplicateOrderId, .. } => {
+                debug!("Resyncing order ids");
+                let _ = self.requests.send(Request::ReqIds { num_ids: 1 });
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::ib::{Message, Request};
+    use crate::socket::ConnectOptions;
+    use crate::socket::pipe;
+    use super::{Client, RequestError};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn responses_go_to_their_request() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket);
+
+        let responses = client.request(|req_id| Request::ReqContractData { req_id, contract: Default::default() });
+        let request_id = responses.request_id.to_string();
+        assert_eq!(gateway.recv()[..3], ["9", "8", request_id.as_str()]);
+
+        gateway.send(&["49", "1", "1704205800"]);
+        gateway.send(&["52", "1", &request_id]);
+
+        assert!(matches!(responses.rx.recv_timeout(TIMEOUT), Ok(Message::ContractDataEnd { .. })));
+        assert!(responses.rx.recv_timeout(TIMEOUT).is_err());
+        assert!(matches!(client.rx.recv_timeout(TIMEOUT), Ok(Message::CurrentTime { .. })));
+    }
+
+    #[test]
+    fn open_orders_until_end() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket).timeout(TIMEOUT);
+
+        let gateway = thread::spawn(move|| {
+            let request = gateway.recv();
+            gateway.send(&["53", "1"]);
+            request
+        });
+
+        assert!(client.open_orders().unwrap().is_empty());
+        assert_eq!(gateway.join().unwrap(), ["5", "1"]);
+    }
+
+    #[test]
+    fn dropping_the_client_closes_the_connection() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket);
+
+        client.send(Request::ReqCurrentTime);
+        assert_eq!(gateway.recv(), ["49", "1"]);
+
+        drop(client);
+        assert!(gateway.try_recv().is_err());
+    }
+
+    #[test]
+    fn pending_requests_fail_when_disconnected() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let client = Client::new(socket).timeout(TIMEOUT);
+
+        let gateway = thread::spawn(move|| {
+            let request = gateway.recv();
+            drop(gateway);
+            request
+        });
+
+        let started = Instant::now();
+        assert!(matches!(client.contract_details(&Default::default()), Err(RequestError::Disconnected)));
+        assert!(started.elapsed() < TIMEOUT);
+        assert_eq!(gateway.join().unwrap()[0], "9");
+        assert!(matches!(client.rx.recv_timeout(TIMEOUT), Ok(Message::Disconnected)));
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Hands out order ids starting at the `NextValidId` sent by TWS.
+///
+/// Ids are only available once TWS has sent `NextValidId`, i.e. after
+/// START_API and again after each reconnect, so `next_timeout` waits until
+/// then. Send `ReqIds` if the allocator is not seeded, e.g. after connecting
+/// without START_API or a lost `NextValidId`.
+/// Ids never decrease, even if TWS reports a lower id after a resync.
+#[derive(Debug, Default)]
+pub struct OrderIdAllocator {
+    state: Mutex<State>,
+    seeded: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: i32,
+    seeded: bool,
+}
+
+impl OrderIdAllocator {
+    /// Waits until the allocator is seeded, or gives up after `timeout`
+    pub fn next_timeout(&self, timeout: Duration) -> Option<i32> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self.seeded.wait_timeout_while(state, timeout, |s| !s.seeded).unwrap();
+
+        if state.seeded {
+            Some(Self::take(state))
+        } else {
+            None
+        }
+    }
+
+    /// True once `NextValidId` has been received on the current connection
+    pub fn is_seeded(&self) -> bool {
+        self.state.lock().unwrap().seeded
+    }
+
+    fn take(mut state: MutexGuard<State>) -> i32 {
+        let id = state.next_id;
+        state.next_id += 1;
+        id
+    }
+
+    /// Called with the id from `NextValidId`
+    pub(crate) fn seed(&self, order_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        state.next_id = state.next_id.max(order_id);
+        state.seeded = true;
+        self.seeded.notify_all();
+    }
+
+    /// Called when the connection is lost. Ids handed out afterwards wait for
+    /// the resync, as other clients may have used ids in the meantime.
+    pub(crate) fn invalidate(&self) {
+        self.state.lock().unwrap().seeded = false;
+    }
+}
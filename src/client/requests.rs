@@ -0,0 +1,182 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use log::{debug, warn};
+
+use crate::ib::{Bar, BarSize, Contract, ContractDetails, ErrorClass, ErrorCode, HistoricalDuration, Message, OpenOrder, Position, Request, WhatToShow};
+
+use super::{Client, HistoricalQuery};
+use super::routes::Collect;
+
+#[derive(Debug)]
+pub enum RequestError {
+    /// `ErrMsg` received for the request
+    Error { code: ErrorCode, message: String },
+    Timeout,
+    Disconnected,
+}
+
+impl std::error::Error for RequestError {}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::Error { code, message } => write!(fmt, "Error {}: {}", i32::from(*code), message),
+            RequestError::Timeout => write!(fmt, "Timeout waiting for response"),
+            RequestError::Disconnected => write!(fmt, "Disconnected"),
+        }
+    }
+}
+
+/// Responses of a request up to and including the *End message, shared by
+/// `Client` and `AsyncClient`
+#[derive(Default)]
+pub(crate) struct Collected(Vec<Message>);
+
+impl Collected {
+    /// Adds `msg`, true once the *End message arrived. Fails on a request
+    /// error.
+    pub(crate) fn push(&mut self, msg: Message) -> Result<bool, RequestError> {
+        match msg {
+            Message::ErrMsg { error_code, error_msg, .. } if error_code.class() == ErrorClass::RequestError => {
+                Err(RequestError::Error { code: error_code, message: error_msg })
+            },
+            msg @ Message::ErrMsg { .. } => {
+                warn!("{:?}", msg);
+                Ok(false)
+            },
+            msg => {
+                let end = msg.is_end() || Collect::is_end(&msg);
+                self.0.push(msg);
+                Ok(end)
+            },
+        }
+    }
+
+    pub(crate) fn contract_details(self) -> Vec<ContractDetails> {
+        self.0.into_iter()
+            .filter_map(|msg| match msg {
+                Message::ContractData { details, .. } => Some(details),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn positions(self) -> Vec<Position> {
+        self.0.into_iter()
+            .filter_map(|msg| match msg {
+                Message::PositionData { account, contract, position, avg_cost, .. } =>
+                    Some(Position { account, contract, position, avg_cost }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn open_orders(self) -> Vec<OpenOrder> {
+        self.0.into_iter()
+            .filter_map(|msg| match msg {
+                Message::OpenOrder(order) => Some(order),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Receives up to and including the *End message, or fails on the first
+/// request error or after `timeout`.
+fn collect(rx: &Receiver<Message>, timeout: Duration) -> Result<Collected, RequestError> {
+    let deadline = Instant::now() + timeout;
+    let mut collected = Collected::default();
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match rx.recv_timeout(remaining) {
+            Ok(msg) => if collected.push(msg)? {
+                return Ok(collected);
+            },
+            Err(RecvTimeoutError::Disconnected) => return Err(RequestError::Disconnected),
+            Err(RecvTimeoutError::Timeout) => return Err(RequestError::Timeout),
+        }
+    }
+}
+
+/// Blocking requests that collect all responses of a request
+impl Client {
+    /// Time to wait for all responses to a blocking request, 30s by default.
+    /// `historical_bars` uses `PacingLimits::timeout` instead.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// All contracts matching `contract`. A contract with a conid is served
+    /// from the `Cache`, if any.
+    pub fn contract_details(&self, contract: &Contract) -> Result<Vec<ContractDetails>, RequestError> {
+        let cache = self.cache.as_ref();
+
+        if contract.conid != 0 {
+            if let Some(details) = cache.and_then(|cache| cache.contract_details(contract.conid)) {
+                return Ok(vec![details]);
+            }
+        }
+
+        let responses = self.request(|req_id| Request::ReqContractData { req_id, contract: contract.clone() });
+        let details = collect(&responses.rx, self.timeout)
+            .map_err(|err| self.cancel(responses.request_id, err))?
+            .contract_details();
+
+        for details in &details {
+            if let Some(Err(err)) = cache.map(|cache| cache.store_contract_details(details)) {
+                warn!("Cannot cache contract {}: {}", details.contract.conid, err);
+            }
+        }
+
+        Ok(details)
+    }
+
+    /// Positions of all accounts. Cancels the positions subscription afterwards.
+    pub fn positions(&self) -> Result<Vec<Position>, RequestError> {
+        let rx = self.collect(Collect::Positions);
+        self.send(Request::ReqPositions);
+
+        let collected = collect(&rx, self.timeout);
+        self.send(Request::CancelPositions);
+
+        Ok(collected?.positions())
+    }
+
+    /// Open orders placed by this client, see `Request::ReqOpenOrders`
+    pub fn open_orders(&self) -> Result<Vec<OpenOrder>, RequestError> {
+        let rx = self.collect(Collect::OpenOrders);
+        self.send(Request::ReqOpenOrders);
+
+        Ok(collect(&rx, self.timeout)?.open_orders())
+    }
+
+    /// Bars of `duration` up to now, with times in seconds since the Unix
+    /// epoch. Paced like `historical_data`; see `historical_range` for longer
+    /// periods.
+    pub fn historical_bars(&self, contract: &Contract, duration: HistoricalDuration, bar_size: BarSize, what_to_show: WhatToShow, use_rth: bool) -> Result<Vec<Bar>, RequestError> {
+        let now: DateTime<Utc> = DateTime::from(std::time::SystemTime::now());
+
+        self.historical_data(&HistoricalQuery {
+            contract: contract.clone(),
+            end_date_time: now.format("%Y%m%d %H:%M:%S GMT").to_string(),
+            duration,
+            bar_size,
+            what_to_show,
+            use_rth,
+            format_date: 2,
+        })
+    }
+
+    /// Stops routing a request that failed or timed out
+    fn cancel(&self, request_id: i32, err: RequestError) -> RequestError {
+        debug!("Request {} failed: {}", request_id, err);
+        self.unregister(request_id);
+        err
+    }
+}
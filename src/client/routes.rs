@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crossbeam_channel::Sender;
+use log::debug;
+
+use crate::ib::{Message, Request};
+
+/// Sending end of a channel that responses are routed to, so `Client` and
+/// `AsyncClient` can share the routing table.
+pub(crate) trait RouteSender: Clone {
+    /// Gives the message back if the receiver is gone
+    #[allow(clippy::result_large_err)]
+    fn send_message(&self, msg: Message) -> Result<(), Message>;
+}
+
+impl RouteSender for Sender<Message> {
+    fn send_message(&self, msg: Message) -> Result<(), Message> {
+        self.send(msg).map_err(|err| err.0)
+    }
+}
+
+#[cfg(feature = "async")]
+impl RouteSender for tokio::sync::mpsc::UnboundedSender<Message> {
+    fn send_message(&self, msg: Message) -> Result<(), Message> {
+        self.send(msg).map_err(|err| err.0)
+    }
+}
+
+/// Channel of a request. A streaming route stays open after the *End
+/// message, e.g. `HistoricalData` of a request that keeps it up to date, and
+/// only closes on a request error or when unregistered.
+#[derive(Clone)]
+struct Route<S> {
+    tx: S,
+    streaming: bool,
+}
+
+impl<S> Route<S> {
+    fn closes_on(&self, msg: &Message) -> bool {
+        msg.is_end() && (!self.streaming || matches!(msg, Message::ErrMsg { .. }))
+    }
+}
+
+/// True for requests whose responses continue after the *End message
+pub(crate) fn is_streaming(request: &Request) -> bool {
+    matches!(request, Request::ReqHistoricalData { keep_up_to_date: true, .. })
+}
+
+/// Responses without a request id that can be collected by kind, e.g. for
+/// `Client::positions`. Account updates have no *End message and are
+/// collected until the receiver is dropped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Collect {
+    Positions,
+    OpenOrders,
+    AccountUpdates,
+}
+
+impl Collect {
+    fn matches(&self, msg: &Message) -> bool {
+        match self {
+            Collect::Positions => matches!(msg, Message::PositionData { .. } | Message::PositionDataEnd { .. }),
+            Collect::OpenOrders => matches!(msg, Message::OpenOrder(_) | Message::OpenOrderEnd { .. }),
+            Collect::AccountUpdates => matches!(msg, Message::AcctValue { .. } | Message::PortfolioValue { .. } |
+                Message::AcctTime { .. } | Message::AcctDownloadEnd { .. }),
+        }
+    }
+
+    pub(crate) fn is_end(msg: &Message) -> bool {
+        matches!(msg, Message::PositionDataEnd { .. } | Message::OpenOrderEnd { .. })
+    }
+}
+
+/// Channels of requests by id and of collected responses by kind, shared
+/// between a client and its dispatcher.
+pub(crate) struct RouteTable<S> {
+    routes: Mutex<HashMap<i32, Route<S>>>,
+    collectors: Mutex<Vec<(Collect, S)>>,
+}
+
+impl<S> Default for RouteTable<S> {
+    fn default() -> RouteTable<S> {
+        RouteTable { routes: Default::default(), collectors: Default::default() }
+    }
+}
+
+impl<S: RouteSender> RouteTable<S> {
+    pub(crate) fn insert(&self, request_id: i32, tx: S, streaming: bool) {
+        self.routes.lock().unwrap().insert(request_id, Route { tx, streaming });
+    }
+
+    pub(crate) fn remove(&self, request_id: i32) {
+        self.routes.lock().unwrap().remove(&request_id);
+    }
+
+    /// Closes all routes and collectors, e.g. when the connection drops, so
+    /// requests waiting for responses fail with `RequestError::Disconnected`
+    pub(crate) fn clear(&self) {
+        self.routes.lock().unwrap().clear();
+        self.collectors.lock().unwrap().clear();
+    }
+
+    /// Sends the next messages of `kind` to `tx`, up to and including the
+    /// *End message
+    pub(crate) fn collect(&self, kind: Collect, tx: S) {
+        self.collectors.lock().unwrap().push((kind, tx));
+    }
+
+    /// Sends `msg` to the route of its request id or else to a collector.
+    /// Returns it if there is neither.
+    pub(crate) fn dispatch(&self, msg: Message) -> Option<Message> {
+        self.route(msg).and_then(|msg| self.collect_message(msg))
+    }
+
+    fn route(&self, msg: Message) -> Option<Message> {
+        let route = msg.request_id().and_then(|id| {
+            let mut routes = self.routes.lock().unwrap();
+            let route = routes.get(&id).cloned()?;
+            if route.closes_on(&msg) {
+                routes.remove(&id);
+            }
+            Some((id, route.tx))
+        });
+
+        let (id, tx) = match route {
+            Some(route) => route,
+            None => return Some(msg),
+        };
+
+        match tx.send_message(msg) {
+            Ok(()) => None,
+            Err(msg) => {
+                debug!("Responses for {} dropped, unregistering", id);
+                self.remove(id);
+                Some(msg)
+            }
+        }
+    }
+
+    /// Sends `msg` to the oldest collector of its kind, dropping collectors
+    /// whose receiver is gone.
+    fn collect_message(&self, mut msg: Message) -> Option<Message> {
+        let mut collectors = self.collectors.lock().unwrap();
+
+        while let Some(i) = collectors.iter().position(|(kind, _)| kind.matches(&msg)) {
+            let end = Collect::is_end(&msg);
+
+            match collectors[i].1.send_message(msg) {
+                Ok(()) => {
+                    if end {
+                        collectors.remove(i);
+                    }
+                    return None;
+                },
+                Err(returned) => {
+                    collectors.remove(i);
+                    msg = returned;
+                }
+            }
+        }
+
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
+
+    use crate::ib::{ErrorCode, Message};
+    use super::{Collect, RouteTable};
+
+    fn contract_data_end(req_id: i32) -> Message {
+        Message::ContractDataEnd { version: 1, req_id }
+    }
+
+    fn historical_data(req_id: i32) -> Message {
+        Message::HistoricalData { req_id, start: String::new(), end: String::new(), bars: Vec::new() }
+    }
+
+    fn route(routes: &RouteTable<Sender<Message>>, request_id: i32, streaming: bool) -> Receiver<Message> {
+        let (tx, rx) = unbounded();
+        routes.insert(request_id, tx, streaming);
+        rx
+    }
+
+    fn collector(routes: &RouteTable<Sender<Message>>, kind: Collect) -> Receiver<Message> {
+        let (tx, rx) = unbounded();
+        routes.collect(kind, tx);
+        rx
+    }
+
+    #[test]
+    fn routes_by_request_id_until_end() {
+        let routes = RouteTable::default();
+        let rx = route(&routes, 1, false);
+
+        assert!(routes.dispatch(contract_data_end(2)).is_some());
+        assert!(routes.dispatch(contract_data_end(1)).is_none());
+
+        assert!(matches!(rx.try_recv(), Ok(Message::ContractDataEnd { req_id: 1, .. })));
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Disconnected);
+        assert!(routes.dispatch(contract_data_end(1)).is_some());
+    }
+
+    #[test]
+    fn streaming_routes_close_on_request_error() {
+        let routes = RouteTable::default();
+        let rx = route(&routes, 1, true);
+
+        assert!(routes.dispatch(historical_data(1)).is_none());
+        assert!(routes.dispatch(historical_data(1)).is_none());
+        assert_eq!(rx.try_iter().count(), 2);
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        let error = Message::ErrMsg {
+            version: 2,
+            id: 1,
+            error_code: ErrorCode::HistoricalDataServiceError,
+            error_msg: "Historical data request cancelled".into(),
+        };
+        assert!(routes.dispatch(error).is_none());
+        assert!(matches!(rx.try_recv(), Ok(Message::ErrMsg { id: 1, .. })));
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Disconnected);
+    }
+
+    #[test]
+    fn dropped_receivers_are_unregistered() {
+        let routes = RouteTable::default();
+        drop(route(&routes, 1, false));
+
+        assert!(matches!(routes.dispatch(historical_data(1)), Some(Message::HistoricalData { req_id: 1, .. })));
+        assert!(routes.routes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn collectors_take_turns_by_kind() {
+        let routes = RouteTable::default();
+        let first = collector(&routes, Collect::Positions);
+        let second = collector(&routes, Collect::Positions);
+        drop(collector(&routes, Collect::OpenOrders));
+
+        assert!(routes.dispatch(Message::PositionDataEnd { version: 1 }).is_none());
+        assert!(routes.dispatch(Message::PositionDataEnd { version: 1 }).is_none());
+        assert!(routes.dispatch(Message::OpenOrderEnd { version: 1 }).is_some());
+
+        assert_eq!(first.try_iter().count(), 1);
+        assert_eq!(first.try_recv().unwrap_err(), TryRecvError::Disconnected);
+        assert_eq!(second.try_iter().count(), 1);
+        assert!(routes.collectors.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn clear_disconnects_routes_and_collectors() {
+        let routes = RouteTable::default();
+        let rx = route(&routes, 1, true);
+        let positions = collector(&routes, Collect::Positions);
+
+        routes.clear();
+
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Disconnected);
+        assert_eq!(positions.try_recv().unwrap_err(), TryRecvError::Disconnected);
+        assert!(routes.dispatch(historical_data(1)).is_some());
+    }
+}
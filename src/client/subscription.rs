@@ -0,0 +1,405 @@
+use std::marker::PhantomData;
+
+use crossbeam_channel::{Receiver, Sender};
+use log::info;
+
+use crate::ib::{Bar, Contract, ErrorClass, Message, Request, ScanData, ScannerSubscription, TagValue, TickByTick, TickByTickType, WhatToShow};
+
+use super::{Client, RequestError, Routes};
+use super::routes::Collect;
+
+/// Typed update of a `Subscription`, decoded from the messages routed to it
+pub trait Update: Sized {
+    /// None for messages without an update, e.g. *End messages
+    fn from_message(msg: Message) -> Option<Self>;
+}
+
+/// Market data of `Client::market_data`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tick {
+    Price { tick_type: i32, price: f64, size: i64, attr_mask: i32 },
+    Size { tick_type: i32, size: i64 },
+    Generic { tick_type: i32, value: f64 },
+    String { tick_type: i32, value: String },
+    /// 1 real-time, 2 frozen, 3 delayed, 4 delayed frozen
+    MarketDataType(i32),
+    Params { min_tick: f64, bbo_exchange: String, snapshot_permissions: i32 },
+}
+
+impl Update for Tick {
+    fn from_message(msg: Message) -> Option<Tick> {
+        match msg {
+            Message::TickPrice { tick_type, price, size, attr_mask, .. } => Some(Tick::Price { tick_type, price, size, attr_mask }),
+            Message::TickSize { tick_type, size, .. } => Some(Tick::Size { tick_type, size }),
+            Message::TickGeneric { tick_type, value, .. } => Some(Tick::Generic { tick_type, value }),
+            Message::TickString { tick_type, value, .. } => Some(Tick::String { tick_type, value }),
+            Message::MarketDataType { market_data_type, .. } => Some(Tick::MarketDataType(market_data_type)),
+            Message::TickReqParams { min_tick, bbo_exchange, snapshot_permissions, .. } =>
+                Some(Tick::Params { min_tick, bbo_exchange, snapshot_permissions }),
+            _ => None,
+        }
+    }
+}
+
+/// Order book change of `Client::market_depth`. `market_maker` is empty for
+/// `MarketDepth`, i.e. without level 2 data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthUpdate {
+    pub position: i32,
+    pub market_maker: String,
+    /// 0 insert, 1 update, 2 delete
+    pub operation: i32,
+    /// 0 ask, 1 bid
+    pub side: i32,
+    pub price: f64,
+    pub size: i64,
+    pub is_smart_depth: bool,
+}
+
+impl Update for DepthUpdate {
+    fn from_message(msg: Message) -> Option<DepthUpdate> {
+        match msg {
+            Message::MarketDepth { position, operation, side, price, size, .. } =>
+                Some(DepthUpdate { position, market_maker: String::new(), operation, side, price, size, is_smart_depth: false }),
+            Message::MarketDepthL2 { position, market_maker, operation, side, price, size, is_smart_depth, .. } =>
+                Some(DepthUpdate { position, market_maker, operation, side, price, size, is_smart_depth }),
+            _ => None,
+        }
+    }
+}
+
+impl Update for Bar {
+    fn from_message(msg: Message) -> Option<Bar> {
+        match msg {
+            Message::RealTimeBars { bar, .. } => Some(bar),
+            _ => None,
+        }
+    }
+}
+
+impl Update for TickByTick {
+    fn from_message(msg: Message) -> Option<TickByTick> {
+        match msg {
+            Message::TickByTick { tick, .. } => Some(tick),
+            _ => None,
+        }
+    }
+}
+
+/// All rows of a scan
+impl Update for Vec<ScanData> {
+    fn from_message(msg: Message) -> Option<Vec<ScanData>> {
+        match msg {
+            Message::ScannerData { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+}
+
+/// Account and portfolio data of `Client::account_updates`
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountUpdate {
+    Value { key: String, value: String, currency: String, account: String },
+    Portfolio { contract: Contract, position: f64, market_price: f64, market_value: f64, average_cost: f64, unrealized_pnl: f64, realized_pnl: f64, account: String },
+    /// Time of the last update, HH:mm
+    Time(String),
+    /// All values of `account` have been sent once
+    DownloadEnd { account: String },
+}
+
+impl Update for AccountUpdate {
+    fn from_message(msg: Message) -> Option<AccountUpdate> {
+        match msg {
+            Message::AcctValue { key, val, cur, account_name, .. } =>
+                Some(AccountUpdate::Value { key, value: val, currency: cur, account: account_name }),
+            Message::PortfolioValue { contract, position, market_price, market_value, average_cost, unrealized_pnl, realized_pnl, account_name, .. } =>
+                Some(AccountUpdate::Portfolio { contract: contract.into(), position, market_price, market_value, average_cost, unrealized_pnl, realized_pnl, account: account_name }),
+            Message::AcctTime { account_time, .. } => Some(AccountUpdate::Time(account_time)),
+            Message::AcctDownloadEnd { account, .. } => Some(AccountUpdate::DownloadEnd { account }),
+            _ => None,
+        }
+    }
+}
+
+/// Updates of a streaming request. Iterating blocks for the next update and
+/// stops when the request ends or fails, see `error`.
+///
+/// Dropping the subscription sends the matching cancel request, unless the
+/// request has ended already, and stops routing its messages.
+pub struct Subscription<T> {
+    request_id: Option<i32>,
+    rx: Receiver<Message>,
+    routes: Routes,
+    requests: Sender<Request>,
+    cancel: Option<Request>,
+    error: Option<RequestError>,
+    update: PhantomData<fn() -> T>,
+}
+
+impl<T: Update> Subscription<T> {
+    /// None for subscriptions without a request id, e.g. account updates
+    pub fn request_id(&self) -> Option<i32> {
+        self.request_id
+    }
+
+    /// Error that ended the subscription, if any
+    pub fn error(&self) -> Option<&RequestError> {
+        self.error.as_ref()
+    }
+
+    /// Next update if one has arrived already
+    pub fn try_next(&mut self) -> Option<T> {
+        while let Ok(msg) = self.rx.try_recv() {
+            if let Some(update) = self.update(msg) {
+                return Some(update);
+            }
+        }
+        None
+    }
+
+    /// Same as dropping the subscription
+    pub fn cancel(self) {}
+
+    fn update(&mut self, msg: Message) -> Option<T> {
+        update(self.request_id, msg, &mut self.cancel, &mut self.error)
+    }
+}
+
+/// Decodes a message of a subscription. Once the request has ended, there
+/// is nothing left to `cancel`, and a request error is kept in `error`.
+/// Shared with the `AsyncClient` subscriptions.
+pub(crate) fn update<T: Update>(request_id: Option<i32>, msg: Message, cancel: &mut Option<Request>, error: &mut Option<RequestError>) -> Option<T> {
+    if msg.is_end() || Collect::is_end(&msg) {
+        *cancel = None;
+    }
+
+    match msg {
+        Message::ErrMsg { error_code, error_msg, .. } if error_code.class() == ErrorClass::RequestError => {
+            *error = Some(RequestError::Error { code: error_code, message: error_msg });
+            None
+        },
+        Message::ErrMsg { .. } => {
+            info!("Subscription {:?}: {:?}", request_id, msg);
+            None
+        },
+        msg => T::from_message(msg),
+    }
+}
+
+impl<T: Update> Iterator for Subscription<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while let Ok(msg) = self.rx.recv() {
+            if let Some(update) = self.update(msg) {
+                return Some(update);
+            }
+        }
+        None
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            // Only fails if the socket is gone, and the request with it
+            let _ = self.requests.send(cancel);
+        }
+        if let Some(request_id) = self.request_id {
+            self.routes.remove(request_id);
+        }
+    }
+}
+
+impl Client {
+    /// Sends the request built with a new id and cancels it with the request
+    /// built by `cancel` when the subscription is dropped
+    fn subscribe<T, F, C>(&self, build: F, cancel: C) -> Subscription<T>
+        where F: FnOnce(i32) -> Request, C: FnOnce(i32) -> Request
+    {
+        let responses = self.request(build);
+
+        Subscription {
+            request_id: Some(responses.request_id),
+            rx: responses.rx,
+            routes: self.routes.clone(),
+            requests: self.socket.tx.clone(),
+            cancel: Some(cancel(responses.request_id)),
+            error: None,
+            update: PhantomData,
+        }
+    }
+
+    /// Streaming market data, or a single snapshot that ends the subscription
+    /// after `TickSnapshotEnd`
+    pub fn market_data(&self, contract: &Contract, generic_tick_list: &str, snapshot: bool) -> Subscription<Tick> {
+        self.subscribe(
+            |ticker_id| Request::ReqMktData {
+                ticker_id,
+                contract: contract.clone(),
+                generic_tick_list: generic_tick_list.into(),
+                snapshot,
+                regulatory_snapshot: false,
+                mkt_data_options: Vec::new(),
+            },
+            |ticker_id| Request::CancelMktData { ticker_id })
+    }
+
+    pub fn market_depth(&self, contract: &Contract, num_rows: i32, is_smart_depth: bool) -> Subscription<DepthUpdate> {
+        self.subscribe(
+            |ticker_id| Request::ReqMktDepth {
+                ticker_id,
+                contract: contract.clone(),
+                num_rows,
+                is_smart_depth,
+                mkt_depth_options: Vec::new(),
+            },
+            |ticker_id| Request::CancelMktDepth { ticker_id, is_smart_depth })
+    }
+
+    /// 5 second bars
+    pub fn real_time_bars(&self, contract: &Contract, what_to_show: WhatToShow, use_rth: bool) -> Subscription<Bar> {
+        self.subscribe(
+            |ticker_id| Request::ReqRealTimeBars {
+                ticker_id,
+                contract: contract.clone(),
+                bar_size: 5,
+                what_to_show,
+                use_rth,
+                real_time_bars_options: Vec::new(),
+            },
+            |ticker_id| Request::CancelRealTimeBars { ticker_id })
+    }
+
+    pub fn tick_by_tick(&self, contract: &Contract, tick_type: TickByTickType, number_of_ticks: i32, ignore_size: bool) -> Subscription<TickByTick> {
+        self.subscribe(
+            |req_id| Request::ReqTickByTickData {
+                req_id,
+                contract: contract.clone(),
+                tick_type,
+                number_of_ticks,
+                ignore_size,
+            },
+            |req_id| Request::CancelTickByTickData { req_id })
+    }
+
+    pub fn scanner(&self, subscription: &ScannerSubscription, filter_options: &[TagValue]) -> Subscription<Vec<ScanData>> {
+        self.subscribe(
+            |ticker_id| Request::ReqScannerSubscription {
+                ticker_id,
+                subscription: subscription.clone(),
+                filter_options: filter_options.to_vec(),
+                options: Vec::new(),
+            },
+            |ticker_id| Request::CancelScannerSubscription { ticker_id })
+    }
+
+    /// Account values and portfolio of `account`. TWS only supports one
+    /// account subscription at a time, a new one replaces the previous one.
+    pub fn account_updates(&self, account: &str) -> Subscription<AccountUpdate> {
+        let rx = self.collect(Collect::AccountUpdates);
+        self.send(Request::ReqAcctData { subscribe: true, acct_code: account.into() });
+
+        Subscription {
+            request_id: None,
+            rx,
+            routes: self.routes.clone(),
+            requests: self.socket.tx.clone(),
+            cancel: Some(Request::ReqAcctData { subscribe: false, acct_code: account.into() }),
+            error: None,
+            update: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ib::{ErrorCode, Request};
+    use crate::socket::ConnectOptions;
+    use crate::socket::pipe::{self, FakeGateway, PipeEnd};
+    use super::{AccountUpdate, Client, RequestError, Tick};
+
+    fn connect() -> (Client, FakeGateway<PipeEnd>) {
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        (Client::new(socket), gateway)
+    }
+
+    /// Checks that nothing was sent before `ReqCurrentTime`
+    fn assert_nothing_sent(client: &Client, gateway: &mut FakeGateway<PipeEnd>) {
+        client.send(Request::ReqCurrentTime);
+        assert_eq!(gateway.recv(), ["49", "1"]);
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_cancelled() {
+        let (client, mut gateway) = connect();
+
+        let mut ticks = client.market_data(&Default::default(), "", false);
+        let request_id = ticks.request_id().unwrap().to_string();
+        assert_eq!(gateway.recv()[..3], ["1", "11", request_id.as_str()]);
+
+        gateway.send(&["1", "6", &request_id, "1", "4.5", "100", "0"]);
+        gateway.send(&["2", "6", &request_id, "0", "200"]);
+        assert_eq!(ticks.next(), Some(Tick::Price { tick_type: 1, price: 4.5, size: 100, attr_mask: 0 }));
+        assert_eq!(ticks.next(), Some(Tick::Size { tick_type: 0, size: 200 }));
+
+        drop(ticks);
+        assert_eq!(gateway.recv(), ["2", "2", request_id.as_str()]);
+    }
+
+    #[test]
+    fn snapshots_are_not_cancelled() {
+        let (client, mut gateway) = connect();
+
+        let mut ticks = client.market_data(&Default::default(), "", true);
+        let request_id = ticks.request_id().unwrap().to_string();
+        gateway.recv();
+
+        gateway.send(&["57", "1", &request_id]);
+        assert!(ticks.next().is_none());
+        assert!(ticks.error().is_none());
+
+        drop(ticks);
+        assert_nothing_sent(&client, &mut gateway);
+    }
+
+    #[test]
+    fn failed_requests_are_not_cancelled() {
+        let (client, mut gateway) = connect();
+
+        let mut ticks = client.market_data(&Default::default(), "", false);
+        let request_id = ticks.request_id().unwrap().to_string();
+        gateway.recv();
+
+        gateway.send(&["4", "2", &request_id, "200", "No security definition has been found for the request"]);
+        assert!(ticks.next().is_none());
+        assert!(matches!(ticks.error(), Some(RequestError::Error { code: ErrorCode::NoSecurityDefinition, .. })));
+
+        drop(ticks);
+        assert_nothing_sent(&client, &mut gateway);
+    }
+
+    #[test]
+    fn account_updates_unsubscribe() {
+        let (client, mut gateway) = connect();
+
+        let mut updates = client.account_updates("DU123");
+        assert_eq!(gateway.recv(), ["6", "2", "1", "DU123"]);
+
+        gateway.send(&["6", "2", "NetLiquidation", "1000.5", "USD", "DU123"]);
+        gateway.send(&["8", "1", "09:30"]);
+        gateway.send(&["54", "1", "DU123"]);
+        assert_eq!(updates.next(), Some(AccountUpdate::Value {
+            key: "NetLiquidation".into(),
+            value: "1000.5".into(),
+            currency: "USD".into(),
+            account: "DU123".into(),
+        }));
+        assert_eq!(updates.next(), Some(AccountUpdate::Time("09:30".into())));
+        assert_eq!(updates.next(), Some(AccountUpdate::DownloadEnd { account: "DU123".into() }));
+
+        drop(updates);
+        assert_eq!(gateway.recv(), ["6", "2", "0", "DU123"]);
+    }
+}
@@ -2,7 +2,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::ib::types::*;
 
+/// Fields not sent in requests are skipped when serializing, and default to
+/// empty when missing, e.g. in cached `ContractDetails`.
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
 pub struct Contract {
     pub conid: i32,
     pub symbol: String,
@@ -73,15 +76,11 @@ pub struct ComboLeg {
     pub action: Action,
 
     pub exchange: String,
-    #[serde(skip_serializing)]
     pub open_close: LegOpenClose, 
 
     /// for stock legs when doing short sale
-    #[serde(skip_serializing)]
     pub short_sale_slot: ShortSaleSlot,
-    #[serde(skip_serializing)]
     pub designated_location: String,
-    #[serde(skip_serializing)]
     pub exempt_code: i32,
 }
 
@@ -126,6 +125,7 @@ pub struct ContractDetails {
     pub market_rule_ids:      String,
     pub real_expiration_date: String,
     pub last_trade_time:      String,
+    pub stock_type:           String,
 
     pub sec_id_list:          Vec<TagValue>,
 
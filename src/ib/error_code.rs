@@ -0,0 +1,210 @@
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+/// Severity of an `ErrMsg`, as documented in the TWS API message codes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorClass {
+    /// Status notifications, e.g. data farm connection OK
+    Informational,
+    /// Something degraded but the request / connection is still alive
+    Warning,
+    /// The request or order referred to by `id` failed
+    RequestError,
+    /// Connectivity between the client, TWS and IB servers is affected
+    ConnectionError,
+}
+
+/// What the `id` field of an `ErrMsg` refers to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorId {
+    /// Ticker / request id of the originating request
+    Request,
+    /// Order id
+    Order,
+    /// Not associated with any request, `id` is -1
+    None,
+    /// Request or order id, for codes not in the catalog
+    Unknown,
+}
+
+macro_rules! error_codes {
+    ($( $(#[$doc:meta])* $name:ident = $code:expr, $class:ident, $id:ident; )*) => {
+        /// Error codes sent in `ErrMsg`. Codes not in the catalog are kept as `Other`.
+        /// Also see https://interactivebrokers.github.io/tws-api/message_codes.html
+        ///
+        /// Codes compare by number, so `Other(100)` equals `MaxRateExceeded`.
+        #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+        #[serde(from="i32", into="i32")]
+        pub enum ErrorCode {
+            $( $(#[$doc])* $name, )*
+            Other(i32),
+        }
+
+        impl From<i32> for ErrorCode {
+            fn from(code: i32) -> ErrorCode {
+                match code {
+                    $( $code => ErrorCode::$name, )*
+                    _ => ErrorCode::Other(code),
+                }
+            }
+        }
+
+        impl From<ErrorCode> for i32 {
+            fn from(code: ErrorCode) -> i32 {
+                match code {
+                    $( ErrorCode::$name => $code, )*
+                    ErrorCode::Other(code) => code,
+                }
+            }
+        }
+
+        impl ErrorCode {
+            /// The catalog entry for `Other` codes that have one
+            pub fn canonical(&self) -> ErrorCode {
+                ErrorCode::from(i32::from(*self))
+            }
+
+            pub fn class(&self) -> ErrorClass {
+                match self.canonical() {
+                    $( ErrorCode::$name => ErrorClass::$class, )*
+                    ErrorCode::Other(code) => match code {
+                        1100..=1300 => ErrorClass::ConnectionError,
+                        2100..=2199 => ErrorClass::Warning,
+                        _ => ErrorClass::RequestError,
+                    },
+                }
+            }
+
+            pub fn id(&self) -> ErrorId {
+                match self.canonical() {
+                    $( ErrorCode::$name => ErrorId::$id, )*
+                    ErrorCode::Other(code) => match code {
+                        1100..=1300 | 2100..=2199 => ErrorId::None,
+                        _ => ErrorId::Unknown,
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for ErrorCode {
+    fn eq(&self, other: &ErrorCode) -> bool {
+        i32::from(*self) == i32::from(*other)
+    }
+}
+
+impl Eq for ErrorCode {}
+
+impl Hash for ErrorCode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        i32::from(*self).hash(state)
+    }
+}
+
+error_codes! {
+    /// Max rate of messages per second has been exceeded
+    MaxRateExceeded = 100, ConnectionError, None;
+    /// Max number of tickers has been reached
+    MaxTickersReached = 101, RequestError, Request;
+    DuplicateTickerId = 102, RequestError, Request;
+    DuplicateOrderId = 103, RequestError, Order;
+    CantModifyFilledOrder = 104, RequestError, Order;
+    OrderModifyMismatch = 105, RequestError, Order;
+    CantTransmitOrder = 106, RequestError, Order;
+    CantTransmitIncompleteOrder = 107, RequestError, Order;
+    /// Price is out of the range defined by the percentage setting
+    PriceOutOfPercentageRange = 109, RequestError, Order;
+    /// The price does not conform to the minimum price variation for this contract
+    InvalidPriceIncrement = 110, RequestError, Order;
+    /// The time in force and the order type are incompatible
+    TifOrderTypeIncompatible = 111, RequestError, Order;
+    SubmitOrderFailed = 133, RequestError, Order;
+    ModifyOrderFailed = 134, RequestError, Order;
+    CantFindOrder = 135, RequestError, Order;
+    OrderCantBeCancelledNow = 136, RequestError, Order;
+    HaltedSecurity = 154, RequestError, Order;
+    CancelNotAllowed = 161, RequestError, Order;
+    /// Historical market data service error, including pacing violations
+    HistoricalDataServiceError = 162, RequestError, Request;
+    HistoricalDataQueryMessage = 165, Informational, Request;
+    NoSecurityDefinition = 200, RequestError, Request;
+    OrderRejected = 201, RequestError, Order;
+    OrderCancelled = 202, Warning, Order;
+    /// The security is not available or allowed for this account
+    SecurityNotAllowed = 203, RequestError, Order;
+    CantFindTickerId = 300, RequestError, Request;
+    MaxMarketDepthRequests = 309, RequestError, Request;
+    MarketDepthReset = 317, Warning, Request;
+    ErrorValidatingRequest = 321, RequestError, Request;
+    ErrorProcessingRequest = 322, RequestError, Request;
+    /// Requested market data is not subscribed
+    NoMarketDataPermissions = 354, RequestError, Request;
+    NoHistoricalDataQuery = 366, RequestError, Request;
+    UnsupportedOrderType = 387, RequestError, Order;
+    OrderMessage = 399, Warning, Order;
+    /// Invalid real-time query, usually a pacing violation
+    InvalidRealTimeQuery = 420, RequestError, Request;
+    OrderSizeZero = 434, RequestError, Order;
+    AlreadyConnected = 501, ConnectionError, None;
+    CouldNotConnect = 502, ConnectionError, None;
+    /// Also sent locally for requests the server version does not support
+    UpdateTws = 503, RequestError, Unknown;
+    NotConnected = 504, ConnectionError, None;
+    FatalError = 505, ConnectionError, None;
+    BadMessageLength = 507, ConnectionError, None;
+    /// Also sent locally for orders that could not be written, see `Socket::connect_supervised`
+    FailSendOrder = 512, RequestError, Order;
+    BadMessage = 509, ConnectionError, None;
+    ConnectivityLost = 1100, ConnectionError, None;
+    ConnectivityRestoredDataLost = 1101, Warning, None;
+    ConnectivityRestoredDataMaintained = 1102, Informational, None;
+    SocketPortReset = 1300, ConnectionError, None;
+    AccountUpdatesUnsubscribed = 2100, Warning, None;
+    MarketDataFarmDisconnected = 2103, Warning, None;
+    MarketDataFarmOk = 2104, Informational, None;
+    HistoricalDataFarmDisconnected = 2105, Warning, None;
+    HistoricalDataFarmOk = 2106, Informational, None;
+    HistoricalDataFarmInactive = 2107, Informational, None;
+    MarketDataFarmInactive = 2108, Informational, None;
+    OutsideRthIgnored = 2109, Warning, Order;
+    TwsServerConnectivityBroken = 2110, Warning, None;
+    MarketDataFarmConnecting = 2119, Informational, None;
+    SecDefFarmDisconnected = 2157, Warning, None;
+    SecDefFarmOk = 2158, Informational, None;
+    MissingParentOrder = 10006, RequestError, Order;
+    OrderToCancelNotFound = 10147, RequestError, Order;
+    OrderCantBeCancelled = 10148, RequestError, Order;
+    /// Requested market data is not subscribed, displaying delayed market data
+    DelayedMarketDataDisplayed = 10167, Warning, Request;
+    /// Requested market data is not subscribed and delayed market data is not enabled
+    DelayedMarketDataNotEnabled = 10168, RequestError, Request;
+    /// No market data during competing live session
+    CompetingLiveSession = 10197, Warning, Request;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn other_codes_are_canonical() {
+        assert_eq!(ErrorCode::Other(100), ErrorCode::MaxRateExceeded);
+        assert_eq!(ErrorCode::Other(100).class(), ErrorClass::ConnectionError);
+        assert_eq!(ErrorCode::Other(103).id(), ErrorId::Order);
+
+        let codes: HashSet<ErrorCode> = vec![ErrorCode::Other(103), ErrorCode::DuplicateOrderId].into_iter().collect();
+        assert_eq!(codes.len(), 1);
+    }
+
+    #[test]
+    fn uncatalogued_codes() {
+        assert_eq!(ErrorCode::from(12345), ErrorCode::Other(12345));
+        assert_eq!(ErrorCode::from(12345).id(), ErrorId::Unknown);
+        assert_eq!(ErrorCode::from(2150).id(), ErrorId::None);
+        assert_eq!(ErrorCode::from(2150).class(), ErrorClass::Warning);
+    }
+}
@@ -1,20 +1,29 @@
+use std::fmt;
+
 use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{self, SeqAccess, Visitor};
 
 use crate::ib::*;
+use crate::protocol::contract::ContractDataMessage;
 use crate::protocol::order::OpenOrderMessage;
+use crate::protocol::version::*;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Hello {
-    server_version: u64,
-    server_connection_time: String,
+    pub server_version: u64,
+    pub server_connection_time: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Message {
-    #[serde(rename="3")]
-    OrderStatus, // TODO
+    #[serde(rename="1")]
+    TickPrice { version: i32, ticker_id: i32, tick_type: i32, price: f64, size: i64, attr_mask: i32 },
+    #[serde(rename="2")]
+    TickSize { version: i32, ticker_id: i32, tick_type: i32, size: i64 },
+    #[serde(rename="3", deserialize_with="decode_3")]
+    OrderStatus { order_id: i32, status: String, filled: f64, remaining: f64, avg_fill_price: f64, perm_id: i32, parent_id: i32, last_fill_price: f64, client_id: i32, why_held: String, mkt_cap_price: f64 },
     #[serde(rename="4")]
-    ErrMsg { version: i32, id: i32, error_code: i32, error_msg: String },
+    ErrMsg { version: i32, id: i32, error_code: ErrorCode, error_msg: String },
     #[serde(rename="5")]
     OpenOrder(OpenOrder),
     #[serde(rename="6")]
@@ -25,17 +34,341 @@ pub enum Message {
     AcctTime { version: i32, account_time: String },
     #[serde(rename="9")]
     NextValidId { version: i32, order_id: i32 },
+    #[serde(rename="10", deserialize_with="decode_10")]
+    ContractData { version: i32, req_id: i32, details: ContractDetails },
+    #[serde(rename="12")]
+    MarketDepth { version: i32, ticker_id: i32, position: i32, operation: i32, side: i32, price: f64, size: i64 },
+    #[serde(rename="13", deserialize_with="decode_13")]
+    MarketDepthL2 { version: i32, ticker_id: i32, position: i32, market_maker: String, operation: i32, side: i32, price: f64, size: i64, is_smart_depth: bool },
     #[serde(rename="15")]
     ManagedAccts { version: i32, accounts_list: String },
+    /// All bars of a `ReqHistoricalData`
+    #[serde(rename="17", deserialize_with="decode_17")]
+    HistoricalData { req_id: i32, start: String, end: String, bars: Vec<Bar> },
+    /// All rows of a scan, sent again whenever the result changes
+    #[serde(rename="20", deserialize_with="decode_20")]
+    ScannerData { version: i32, ticker_id: i32, data: Vec<ScanData> },
+    #[serde(rename="45")]
+    TickGeneric { version: i32, ticker_id: i32, tick_type: i32, value: f64 },
+    #[serde(rename="46")]
+    TickString { version: i32, ticker_id: i32, tick_type: i32, value: String },
+    /// Server time in seconds since the Unix epoch
+    #[serde(rename="49")]
+    CurrentTime { version: i32, time: i64 },
+    #[serde(rename="50", deserialize_with="decode_50")]
+    RealTimeBars { version: i32, req_id: i32, bar: Bar },
+    #[serde(rename="52")]
+    ContractDataEnd { version: i32, req_id: i32 },
+    /// Sent after all `OpenOrder`s in response to `ReqOpenOrders` / `ReqAllOpenOrders`
+    #[serde(rename="53")]
+    OpenOrderEnd { version: i32 },
     #[serde(rename="54")]
     AcctDownloadEnd { version: i32, account: String },
     #[serde(rename="61", deserialize_with="decode_61")]
     PositionData { version: i32, account: String, contract: Contract, position: f64, avg_cost: f64 },
     #[serde(rename="62")]
     PositionDataEnd { version: i32 },
+    /// Sent after the ticks of a snapshot `ReqMktData`
+    #[serde(rename="57")]
+    TickSnapshotEnd { version: i32, req_id: i32 },
+    #[serde(rename="58")]
+    MarketDataType { version: i32, req_id: i32, market_data_type: i32 },
+    #[serde(rename="81")]
+    TickReqParams { ticker_id: i32, min_tick: f64, bbo_exchange: String, snapshot_permissions: i32 },
+    /// Sent after `HistoricalData` if `keep_up_to_date` was requested
+    #[serde(rename="90", deserialize_with="decode_90")]
+    HistoricalDataUpdate { req_id: i32, bar: Bar },
+    #[serde(rename="99", deserialize_with="decode_99")]
+    TickByTick { req_id: i32, tick: TickByTick },
+
+    /// Not actual IB message, sent by a supervised `Socket` once connected
+    Connected { server_version: u64 },
+    /// Not actual IB message, sent when the connection is lost. A supervised
+    /// `Socket` queues requests until reconnected.
+    Disconnected,
+    /// Not actual IB message, sent by a supervised `Socket` after reconnecting.
+    /// Active subscriptions are sent again ahead of queued requests.
+    Reconnected { server_version: u64 },
+
+    /// Not actual IB message, used to encode an unknown message. Contains all
+    /// fields of the frame, starting with the message id.
+    UnknownMessage(Vec<String>),
+    /// Not actual IB message, used when a known message fails to decode.
+    /// Contains the error and all fields of the frame.
+    DecodeFailed { error: String, fields: Vec<String> },
+}
+
+impl Message {
+    /// Ticker / request id of the request this message responds to, if any.
+    /// `ErrMsg` only has one if its code refers to a request rather than an
+    /// order, or might, see `ErrorId::Unknown`.
+    pub fn request_id(&self) -> Option<i32> {
+        use Message::*;
+
+        match self {
+            ErrMsg { id, error_code, .. } if *id != -1 && matches!(error_code.id(), ErrorId::Request | ErrorId::Unknown) => Some(*id),
+            TickPrice { ticker_id, .. } |
+            TickSize { ticker_id, .. } |
+            TickGeneric { ticker_id, .. } |
+            TickString { ticker_id, .. } |
+            TickReqParams { ticker_id, .. } |
+            MarketDepth { ticker_id, .. } |
+            MarketDepthL2 { ticker_id, .. } |
+            ScannerData { ticker_id, .. } => Some(*ticker_id),
+            ContractData { req_id, .. } |
+            ContractDataEnd { req_id, .. } |
+            HistoricalData { req_id, .. } |
+            HistoricalDataUpdate { req_id, .. } |
+            RealTimeBars { req_id, .. } |
+            TickByTick { req_id, .. } |
+            TickSnapshotEnd { req_id, .. } |
+            MarketDataType { req_id, .. } => Some(*req_id),
+            _ => None,
+        }
+    }
+
+    /// Id of the order this message is about, if any. Like `request_id`, an
+    /// `ErrMsg` with a code not in the catalog has both.
+    pub fn order_id(&self) -> Option<i32> {
+        use Message::*;
+
+        match self {
+            ErrMsg { id, error_code, .. } if *id != -1 && matches!(error_code.id(), ErrorId::Order | ErrorId::Unknown) => Some(*id),
+            OrderStatus { order_id, .. } => Some(*order_id),
+            OpenOrder(open) => Some(open.order.order_id),
+            _ => None,
+        }
+    }
+
+    /// Name of the variant, e.g. "TickPrice"
+    pub fn kind(&self) -> &'static str {
+        use Message::*;
+
+        match self {
+            TickPrice { .. } => "TickPrice",
+            TickSize { .. } => "TickSize",
+            OrderStatus { .. } => "OrderStatus",
+            ErrMsg { .. } => "ErrMsg",
+            OpenOrder(_) => "OpenOrder",
+            AcctValue { .. } => "AcctValue",
+            PortfolioValue { .. } => "PortfolioValue",
+            AcctTime { .. } => "AcctTime",
+            NextValidId { .. } => "NextValidId",
+            ContractData { .. } => "ContractData",
+            MarketDepth { .. } => "MarketDepth",
+            MarketDepthL2 { .. } => "MarketDepthL2",
+            ManagedAccts { .. } => "ManagedAccts",
+            HistoricalData { .. } => "HistoricalData",
+            ScannerData { .. } => "ScannerData",
+            TickGeneric { .. } => "TickGeneric",
+            TickString { .. } => "TickString",
+            CurrentTime { .. } => "CurrentTime",
+            RealTimeBars { .. } => "RealTimeBars",
+            ContractDataEnd { .. } => "ContractDataEnd",
+            OpenOrderEnd { .. } => "OpenOrderEnd",
+            AcctDownloadEnd { .. } => "AcctDownloadEnd",
+            PositionData { .. } => "PositionData",
+            PositionDataEnd { .. } => "PositionDataEnd",
+            TickSnapshotEnd { .. } => "TickSnapshotEnd",
+            MarketDataType { .. } => "MarketDataType",
+            TickReqParams { .. } => "TickReqParams",
+            HistoricalDataUpdate { .. } => "HistoricalDataUpdate",
+            TickByTick { .. } => "TickByTick",
+            Connected { .. } => "Connected",
+            Disconnected => "Disconnected",
+            Reconnected { .. } => "Reconnected",
+            UnknownMessage(_) => "UnknownMessage",
+            DecodeFailed { .. } => "DecodeFailed",
+        }
+    }
+
+    /// Account the message is about, if any
+    pub fn account(&self) -> Option<&str> {
+        use Message::*;
+
+        match self {
+            AcctValue { account_name, .. } |
+            PortfolioValue { account_name, .. } => Some(account_name),
+            AcctDownloadEnd { account, .. } |
+            PositionData { account, .. } => Some(account),
+            OpenOrder(open) => Some(&open.order.account),
+            _ => None,
+        }
+    }
+
+    /// Contract id of the contract the message is about, if any
+    pub fn conid(&self) -> Option<i32> {
+        use Message::*;
+
+        match self {
+            PortfolioValue { contract, .. } => Some(contract.conid),
+            PositionData { contract, .. } => Some(contract.conid),
+            OpenOrder(open) => Some(open.contract.conid),
+            ContractData { details, .. } => Some(details.contract.conid),
+            _ => None,
+        }
+    }
+
+    /// True if no further messages follow for `request_id()`, either because
+    /// this is the *End message of the request or because the request failed.
+    pub fn is_end(&self) -> bool {
+        match self {
+            Message::ErrMsg { error_code, .. } => error_code.class() == ErrorClass::RequestError,
+            Message::ContractDataEnd { .. } |
+            Message::HistoricalData { .. } |
+            Message::TickSnapshotEnd { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_3<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, String, f64, f64, f64, i32, i32, f64, i32, String, f64), D::Error> {
+    #[derive(Deserialize)]
+    struct Message3 {
+        _version: Until<i32, MIN_SERVER_VER_MARKET_CAP_PRICE>,
+        order_id: i32,
+        status: String,
+        filled: f64,
+        remaining: f64,
+        avg_fill_price: f64,
+        perm_id: i32,
+        parent_id: i32,
+        last_fill_price: f64,
+        client_id: i32,
+        why_held: String,
+        mkt_cap_price: Since<f64, MIN_SERVER_VER_MARKET_CAP_PRICE>,
+    }
+
+    Message3::deserialize(deserializer)
+        .map(|m| (m.order_id, m.status, m.filled, m.remaining, m.avg_fill_price, m.perm_id, m.parent_id, m.last_fill_price, m.client_id, m.why_held, m.mkt_cap_price.into_inner().unwrap_or_default()))
+}
+
+fn decode_10<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, i32, ContractDetails), D::Error> {
+    ContractDataMessage::deserialize(deserializer)
+        .map(|m| (m.version, m.req_id, m.into()))
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_13<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, i32, i32, String, i32, i32, f64, i64, bool), D::Error> {
+    #[derive(Deserialize)]
+    struct Message13 {
+        version: i32,
+        ticker_id: i32,
+        position: i32,
+        market_maker: String,
+        operation: i32,
+        side: i32,
+        price: f64,
+        size: i64,
+        is_smart_depth: Since<bool, MIN_SERVER_VER_SMART_DEPTH>,
+    }
+
+    Message13::deserialize(deserializer)
+        .map(|m| (m.version, m.ticker_id, m.position, m.market_maker, m.operation, m.side, m.price, m.size, m.is_smart_depth.into_inner().unwrap_or_default()))
+}
+
+fn decode_17<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, String, String, Vec<Bar>), D::Error> {
+    #[derive(Deserialize)]
+    struct Row {
+        time: String,
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        volume: i64,
+        wap: f64,
+        _has_gaps: Until<String, MIN_SERVER_VER_SYNT_REALTIME_BARS>,
+        count: i32,
+    }
+
+    #[derive(Deserialize)]
+    struct Message17 {
+        _version: Until<i32, MIN_SERVER_VER_SYNT_REALTIME_BARS>,
+        req_id: i32,
+        start: String,
+        end: String,
+        bars: Vec<Row>,
+    }
+
+    Message17::deserialize(deserializer)
+        .map(|m| {
+            let bars = m.bars.into_iter()
+                .map(|r| Bar { time: r.time, open: r.open, high: r.high, low: r.low, close: r.close, volume: r.volume, wap: r.wap, count: r.count })
+                .collect();
+            (m.req_id, m.start, m.end, bars)
+        })
+}
+
+fn decode_20<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, i32, Vec<ScanData>), D::Error> {
+    #[derive(Deserialize)]
+    struct Row {
+        rank: i32,
+        conid: i32,
+        symbol: String,
+        sec_type: String,
+        last_trade_date_or_contract_month: String,
+        strike: f64,
+        right: Right,
+        exchange: String,
+        currency: String,
+        local_symbol: String,
+        market_name: String,
+        trading_class: String,
+        distance: String,
+        benchmark: String,
+        projection: String,
+        legs: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Message20 {
+        version: i32,
+        ticker_id: i32,
+        rows: Vec<Row>,
+    }
 
-    /// Not actual IB message, used to encode an unknown message
-    UnknownMessage(String),
+    Message20::deserialize(deserializer)
+        .map(|m| (m.version, m.ticker_id, m.rows.into_iter().map(|r| ScanData {
+            rank: r.rank,
+            contract: Contract {
+                conid: r.conid,
+                symbol: r.symbol,
+                sec_type: r.sec_type,
+                last_trade_date_or_contract_month: r.last_trade_date_or_contract_month,
+                strike: r.strike,
+                right: r.right,
+                exchange: r.exchange,
+                currency: r.currency,
+                local_symbol: r.local_symbol,
+                trading_class: r.trading_class,
+                .. Default::default()
+            },
+            market_name: r.market_name,
+            distance: r.distance,
+            benchmark: r.benchmark,
+            projection: r.projection,
+            legs: r.legs,
+        }).collect()))
+}
+
+fn decode_50<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, i32, Bar), D::Error> {
+    #[derive(Deserialize)]
+    struct Message50 {
+        version: i32,
+        req_id: i32,
+        time: String,
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        volume: i64,
+        wap: f64,
+        count: i32,
+    }
+
+    Message50::deserialize(deserializer)
+        .map(|m| (m.version, m.req_id, Bar { time: m.time, open: m.open, high: m.high, low: m.low, close: m.close, volume: m.volume, wap: m.wap, count: m.count }))
 }
 
 fn decode_61<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, String, Contract, f64, f64), D::Error> {
@@ -52,6 +385,75 @@ fn decode_61<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, String,
         .map(|m| (m.version, m.account, m.contract.into(), m.position, m.avg_cost))
 }
 
+fn decode_90<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, Bar), D::Error> {
+    #[derive(Deserialize)]
+    struct Message90 {
+        req_id: i32,
+        count: i32,
+        time: String,
+        open: f64,
+        close: f64,
+        high: f64,
+        low: f64,
+        wap: f64,
+        volume: i64,
+    }
+
+    Message90::deserialize(deserializer)
+        .map(|m| (m.req_id, Bar { time: m.time, open: m.open, high: m.high, low: m.low, close: m.close, volume: m.volume, wap: m.wap, count: m.count }))
+}
+
+/// The fields after the tick type depend on it, so they are read one by one
+fn decode_99<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(i32, TickByTick), D::Error> {
+    struct Message99;
+
+    fn next<'de, A: SeqAccess<'de>, T: Deserialize<'de>>(seq: &mut A) -> Result<T, A::Error> {
+        seq.next_element()?.ok_or_else(|| de::Error::custom("missing tick-by-tick field"))
+    }
+
+    impl<'de> Visitor<'de> for Message99 {
+        type Value = (i32, TickByTick);
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmt, "TICK_BY_TICK")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let req_id = next(&mut seq)?;
+            let tick_type: i32 = next(&mut seq)?;
+            let time = next(&mut seq)?;
+
+            let tick = match tick_type {
+                1 | 2 => {
+                    let (price, size, attr_mask, exchange, special_conditions) =
+                        (next(&mut seq)?, next(&mut seq)?, next(&mut seq)?, next(&mut seq)?, next(&mut seq)?);
+
+                    if tick_type == 1 {
+                        TickByTick::Last { time, price, size, attr_mask, exchange, special_conditions }
+                    } else {
+                        TickByTick::AllLast { time, price, size, attr_mask, exchange, special_conditions }
+                    }
+                },
+                3 => TickByTick::BidAsk {
+                    time,
+                    bid_price: next(&mut seq)?,
+                    ask_price: next(&mut seq)?,
+                    bid_size: next(&mut seq)?,
+                    ask_size: next(&mut seq)?,
+                    attr_mask: next(&mut seq)?,
+                },
+                4 => TickByTick::MidPoint { time, mid_point: next(&mut seq)? },
+                _ => return Err(de::Error::custom(format!("unknown tick-by-tick type {}", tick_type))),
+            };
+
+            Ok((req_id, tick))
+        }
+    }
+
+    // Longest layout: req id, type, time and 5 fields
+    deserializer.deserialize_tuple(8, Message99)
+}
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct PositionDataContract {
     pub conid: i32,
@@ -120,6 +522,15 @@ impl From<PortfolioValueContract> for Contract {
     }
 }
 
+/// A position as reported in `PositionData`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Position {
+    pub account: String,
+    pub contract: Contract,
+    pub position: f64,
+    pub avg_cost: f64,
+}
+
 /// Note that the official client has 2 kinds of optional fields. The explicit optional field uses
 /// i32/f64::MAX to indicate a None value, while all other numeric/bool fields default to 0 if server
 /// sends "" (which becomes false for bool)
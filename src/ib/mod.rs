@@ -1,13 +1,17 @@
 pub mod contract;
+pub mod error_code;
 pub mod message;
 pub mod order;
 pub mod order_condition;
 pub mod request;
+pub mod scanner;
 pub mod types;
 
 pub use contract::{Contract, ContractDescription, ContractDetails};
-pub use message::{Hello, Message};
+pub use error_code::{ErrorClass, ErrorCode, ErrorId};
+pub use message::{Hello, Message, OpenOrder, Position};
 pub use order::{Order, OrderState};
 pub use order_condition::{AndOr, OrderCondition};
 pub use request::Request;
+pub use scanner::{ScanData, ScannerSubscription};
 pub use types::*;
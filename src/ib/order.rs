@@ -27,8 +27,7 @@ pub enum OrderType {
     MOC,
     MTL,
     #[serde(rename="PASSV REL")] PASSV_REL,
-    // TODO not supported because we can't dynamically read PegToBenchParams
-    // #[serde(rename="PEG BENCH")] PEG_BENCH,
+    #[serde(rename="PEG BENCH")] PEG_BENCH,
     #[serde(rename="PEG MID")] PEG_MID,
     #[serde(rename="PEG MKT")] PEG_MKT,
     #[serde(rename="PEG PRIM")] PEG_PRIM,
@@ -10,13 +10,23 @@ pub enum AndOr {
     #[serde(rename="o")] Or,
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SmartDefault)]
+/// Fields are in wire order. `is_more` is true if the condition triggers when
+/// the value goes above the threshold, false if below.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, SmartDefault)]
 pub enum OrderCondition {
     #[default]
-    #[serde(rename="1")] Price { and_or: AndOr, trigger_method: TriggerMethod },
-    #[serde(rename="3")] Time { and_or: AndOr },
-    #[serde(rename="4")] Margin { and_or: AndOr },
-    #[serde(rename="5")] Execution { and_or: AndOr, sec_type: String, exchange: String, symbol: String },
-    #[serde(rename="6")] Volume { and_or: AndOr },
-    #[serde(rename="7")] PercentChange { and_or: AndOr },
+    #[serde(rename="1")]
+    Price { and_or: AndOr, is_more: bool, price: f64, conid: i32, exchange: String, trigger_method: TriggerMethod },
+    /// Format: 20060505 08:00:00 EST
+    #[serde(rename="3")]
+    Time { and_or: AndOr, is_more: bool, time: String },
+    /// Margin cushion in percent
+    #[serde(rename="4")]
+    Margin { and_or: AndOr, is_more: bool, percent: i32 },
+    #[serde(rename="5")]
+    Execution { and_or: AndOr, sec_type: String, exchange: String, symbol: String },
+    #[serde(rename="6")]
+    Volume { and_or: AndOr, is_more: bool, volume: i32, conid: i32, exchange: String },
+    #[serde(rename="7")]
+    PercentChange { and_or: AndOr, is_more: bool, change_percent: f64, conid: i32, exchange: String },
 }
@@ -1,41 +1,206 @@
 use serde::{Deserialize, Serialize, Serializer};
 use smart_default::SmartDefault;
 
-use crate::ib::{Contract, TagValue};
+use crate::ib::{BarSize, Contract, HistoricalDuration, Order, ScannerSubscription, TagValue, TickByTickType, WhatToShow};
+use crate::protocol::contract::{BagComboLegs, DeltaNeutralContractField};
+use crate::protocol::order::PlaceOrderMessage;
+use crate::protocol::version::*;
 
 /// Outgoing messages. We use serde rename to the right ID + Version.
 /// Also see EClient.h / EClient.cpp
-#[derive(Debug, Deserialize, Serialize, SmartDefault)]
+#[derive(Clone, Debug, Deserialize, Serialize, SmartDefault)]
 pub enum Request {
     #[default] None,
     #[serde(rename="1\011", serialize_with="req_mkt_data")]
     ReqMktData { ticker_id: i32, contract: Contract, generic_tick_list: String, snapshot: bool, regulatory_snapshot: bool, mkt_data_options: Vec<TagValue> },
+    #[serde(rename="2\02")]
+    CancelMktData { ticker_id: i32 },
+    #[serde(rename="3", serialize_with="place_order")]
+    PlaceOrder { order_id: i32, contract: Contract, order: Order },
     #[serde(rename="5\01")]
     ReqOpenOrders,
     #[serde(rename="6\02")]
     ReqAcctData { subscribe: bool, acct_code: String },
+    #[serde(rename="8\01")]
+    ReqIds { num_ids: i32 },
+    #[serde(rename="9\08", serialize_with="req_contract_data")]
+    ReqContractData { req_id: i32, contract: Contract },
+    #[serde(rename="10\05", serialize_with="req_mkt_depth")]
+    ReqMktDepth { ticker_id: i32, contract: Contract, num_rows: i32, is_smart_depth: bool, mkt_depth_options: Vec<TagValue> },
+    #[serde(rename="11\01", serialize_with="cancel_mkt_depth")]
+    CancelMktDepth { ticker_id: i32, is_smart_depth: bool },
+    /// Only valid for client id 0. TWS orders will be bound to this client and
+    /// given an API order id.
+    #[serde(rename="15\01")]
+    ReqAutoOpenOrders { auto_bind: bool },
     #[serde(rename="16\01")]
     ReqAllOpenOrders,
+    /// `format_date` 1 returns bar times as yyyyMMdd HH:mm:ss, 2 as seconds since
+    /// the Unix epoch. `keep_up_to_date` needs server version 124 or later.
+    #[serde(rename="20", serialize_with="req_historical_data")]
+    ReqHistoricalData { ticker_id: i32, contract: Contract, end_date_time: String, bar_size: BarSize, duration: HistoricalDuration, use_rth: bool, what_to_show: WhatToShow, format_date: i32, keep_up_to_date: bool, chart_options: Vec<TagValue> },
+    /// `filter_options` need server version 143 or later.
+    #[serde(rename="22", serialize_with="req_scanner_subscription")]
+    ReqScannerSubscription { ticker_id: i32, subscription: ScannerSubscription, filter_options: Vec<TagValue>, options: Vec<TagValue> },
+    #[serde(rename="23\01")]
+    CancelScannerSubscription { ticker_id: i32 },
+    #[serde(rename="25\01")]
+    CancelHistoricalData { ticker_id: i32 },
+    #[serde(rename="49\01")]
+    ReqCurrentTime,
+    /// Only 5 second bars are supported by TWS
+    #[serde(rename="50\03", serialize_with="req_real_time_bars")]
+    ReqRealTimeBars { ticker_id: i32, contract: Contract, bar_size: i32, what_to_show: WhatToShow, use_rth: bool, real_time_bars_options: Vec<TagValue> },
+    #[serde(rename="51\01")]
+    CancelRealTimeBars { ticker_id: i32 },
     #[serde(rename="61\01")]
     ReqPositions,
+    #[serde(rename="64\01")]
+    CancelPositions,
     #[serde(rename="71\02")]
     StartApi { client_id: i32, optional_capabilities: String },
+    /// `number_of_ticks` > 0 requests historical ticks first. Needs server
+    /// version 137 or later, 140 for `number_of_ticks` and `ignore_size`.
+    #[serde(rename="97", serialize_with="req_tick_by_tick_data")]
+    ReqTickByTickData { req_id: i32, contract: Contract, tick_type: TickByTickType, number_of_ticks: i32, ignore_size: bool },
+    #[serde(rename="98")]
+    CancelTickByTickData { req_id: i32 },
 }
 
-fn req_mkt_data<S: Serializer>(ticker_id: &i32, contract: &Contract, generic_tick_list: &String, snapshot: &bool, regulatory_snapshot: &bool, mkt_data_options: &Vec<TagValue>, s: S) -> Result<S::Ok, S::Error> {
-    let has_delta_neutral_contract = contract.delta_neutral_contract.is_some();
+impl Request {
+    /// Ticker, request or order id, if the request has one
+    pub fn id(&self) -> Option<i32> {
+        match *self {
+            Request::ReqMktData { ticker_id, .. } |
+            Request::CancelMktData { ticker_id } |
+            Request::ReqMktDepth { ticker_id, .. } |
+            Request::CancelMktDepth { ticker_id, .. } |
+            Request::ReqHistoricalData { ticker_id, .. } |
+            Request::ReqScannerSubscription { ticker_id, .. } |
+            Request::CancelScannerSubscription { ticker_id } |
+            Request::CancelHistoricalData { ticker_id } |
+            Request::ReqRealTimeBars { ticker_id, .. } |
+            Request::CancelRealTimeBars { ticker_id } => Some(ticker_id),
+            Request::PlaceOrder { order_id, .. } => Some(order_id),
+            Request::ReqContractData { req_id, .. } |
+            Request::ReqTickByTickData { req_id, .. } |
+            Request::CancelTickByTickData { req_id } => Some(req_id),
+            _ => None,
+        }
+    }
+}
 
+fn req_mkt_data<S: Serializer>(ticker_id: &i32, contract: &Contract, generic_tick_list: &String, snapshot: &bool, regulatory_snapshot: &bool, mkt_data_options: &[TagValue], s: S) -> Result<S::Ok, S::Error> {
     (
         ticker_id,
         contract,
-        &contract.combo_legs,
-        has_delta_neutral_contract,
-        //&contract.delta_neutral_contract,
+        BagComboLegs(contract),
+        DeltaNeutralContractField(&contract.delta_neutral_contract),
         generic_tick_list,
         snapshot,
-        regulatory_snapshot,
-        mkt_data_options
+        Since::<_, MIN_SERVER_VER_REQ_SMART_COMPONENTS>::from(regulatory_snapshot),
+        tag_value_list(mkt_data_options)
+    ).serialize(s)
+}
+
+fn req_mkt_depth<S: Serializer>(ticker_id: &i32, contract: &Contract, num_rows: &i32, is_smart_depth: &bool, mkt_depth_options: &[TagValue], s: S) -> Result<S::Ok, S::Error> {
+    (
+        ticker_id,
+        (
+            contract.conid,
+            &contract.symbol,
+            &contract.sec_type,
+            &contract.last_trade_date_or_contract_month,
+            contract.strike,
+            &contract.right,
+            &contract.multiplier,
+            &contract.exchange,
+            Since::<_, MIN_SERVER_VER_MKT_DEPTH_PRIM_EXCHANGE>::from(&contract.primary_exch),
+            &contract.currency,
+            &contract.local_symbol,
+            &contract.trading_class,
+        ),
+        num_rows,
+        Since::<_, MIN_SERVER_VER_SMART_DEPTH>::from(is_smart_depth),
+        tag_value_list(mkt_depth_options)
+    ).serialize(s)
+}
+
+fn cancel_mkt_depth<S: Serializer>(ticker_id: &i32, is_smart_depth: &bool, s: S) -> Result<S::Ok, S::Error> {
+    (
+        ticker_id,
+        Since::<_, MIN_SERVER_VER_SMART_DEPTH>::from(is_smart_depth)
+    ).serialize(s)
+}
+
+fn req_contract_data<S: Serializer>(req_id: &i32, contract: &Contract, s: S) -> Result<S::Ok, S::Error> {
+    (
+        req_id,
+        contract,
+        contract.include_expired,
+        &contract.sec_id_type,
+        &contract.sec_id
+    ).serialize(s)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn req_historical_data<S: Serializer>(ticker_id: &i32, contract: &Contract, end_date_time: &String, bar_size: &BarSize, duration: &HistoricalDuration, use_rth: &bool, what_to_show: &WhatToShow, format_date: &i32, keep_up_to_date: &bool, chart_options: &[TagValue], s: S) -> Result<S::Ok, S::Error> {
+    (
+        Until::<i32, MIN_SERVER_VER_SYNT_REALTIME_BARS>::from(6),
+        ticker_id,
+        contract,
+        contract.include_expired,
+        end_date_time,
+        bar_size,
+        duration,
+        use_rth,
+        what_to_show,
+        format_date,
+        BagComboLegs(contract),
+        Since::<_, MIN_SERVER_VER_SYNT_REALTIME_BARS>::from(keep_up_to_date),
+        tag_value_list(chart_options)
+    ).serialize(s)
+}
+
+fn req_scanner_subscription<S: Serializer>(ticker_id: &i32, subscription: &ScannerSubscription, filter_options: &[TagValue], options: &[TagValue], s: S) -> Result<S::Ok, S::Error> {
+    (
+        Until::<i32, MIN_SERVER_VER_SCANNER_GENERIC_OPTS>::from(4),
+        ticker_id,
+        subscription,
+        Since::<_, MIN_SERVER_VER_SCANNER_GENERIC_OPTS>::from(tag_value_list(filter_options)),
+        tag_value_list(options)
     ).serialize(s)
 }
 
+fn req_real_time_bars<S: Serializer>(ticker_id: &i32, contract: &Contract, bar_size: &i32, what_to_show: &WhatToShow, use_rth: &bool, real_time_bars_options: &[TagValue], s: S) -> Result<S::Ok, S::Error> {
+    (
+        ticker_id,
+        contract,
+        bar_size,
+        what_to_show,
+        use_rth,
+        tag_value_list(real_time_bars_options)
+    ).serialize(s)
+}
+
+fn req_tick_by_tick_data<S: Serializer>(req_id: &i32, contract: &Contract, tick_type: &TickByTickType, number_of_ticks: &i32, ignore_size: &bool, s: S) -> Result<S::Ok, S::Error> {
+    (
+        Requires::<MIN_SERVER_VER_TICK_BY_TICK>,
+        req_id,
+        contract,
+        tick_type,
+        Since::<_, MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE>::from(number_of_ticks),
+        Since::<_, MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE>::from(ignore_size)
+    ).serialize(s)
+}
+
+/// Options are sent as a single "tag=value;" string
+fn tag_value_list(options: &[TagValue]) -> String {
+    options.iter().map(|o| format!("{}={};", o.tag, o.value)).collect()
+}
+
+fn place_order<S: Serializer>(order_id: &i32, contract: &Contract, order: &Order, s: S) -> Result<S::Ok, S::Error> {
+    PlaceOrderMessage::new(*order_id, contract, order).serialize(s)
+}
+
 //1-11-1-383430121-ESTC-OPT-20200717-60-P-100-SMART--USD-ESTC  200717P00060000-ESTC-0-0--221-0-
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ib::Contract;
+
+/// Parameters of a `ReqScannerSubscription`, see `ScannerSubscription.h`.
+/// Fields left at `None` or empty are not used for filtering.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ScannerSubscription {
+    pub number_of_rows: Option<i32>,
+    pub instrument: String,
+    pub location_code: String,
+    pub scan_code: String,
+    pub above_price: Option<f64>,
+    pub below_price: Option<f64>,
+    pub above_volume: Option<i32>,
+    pub market_cap_above: Option<f64>,
+    pub market_cap_below: Option<f64>,
+    pub moody_rating_above: String,
+    pub moody_rating_below: String,
+    pub sp_rating_above: String,
+    pub sp_rating_below: String,
+    pub maturity_date_above: String,
+    pub maturity_date_below: String,
+    pub coupon_rate_above: Option<f64>,
+    pub coupon_rate_below: Option<f64>,
+    pub exclude_convertible: bool,
+    pub average_option_volume_above: Option<i32>,
+    pub scanner_setting_pairs: String,
+    pub stock_type_filter: String,
+}
+
+/// One row of `ScannerData`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ScanData {
+    pub rank: i32,
+    pub contract: Contract,
+    pub market_name: String,
+    pub distance: String,
+    pub benchmark: String,
+    pub projection: String,
+    pub legs: String,
+}
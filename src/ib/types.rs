@@ -1,3 +1,7 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use smart_default::SmartDefault;
 
@@ -143,14 +147,14 @@ pub enum FundamentalType {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum WhatToShow {
     TRADES, MIDPOINT, BID, ASK, // << only these are valid for real-time bars
     BID_ASK, HISTORICAL_VOLATILITY, OPTION_IMPLIED_VOLATILITY, YIELD_ASK, YIELD_BID, YIELD_BID_ASK, YIELD_LAST, ADJUSTED_LAST
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum BarSize {
     #[serde(rename="1 secs")] _1_secs,
     #[serde(rename="5 secs")] _5_secs,
@@ -172,12 +176,100 @@ pub enum BarSize {
     #[serde(rename="1 month")] _1_month,
 }
 
+impl BarSize {
+    /// Longest duration IB allows in one historical data request for this bar size
+    pub fn max_duration(&self) -> HistoricalDuration {
+        use BarSize::*;
+        use DurationUnit::*;
+
+        match self {
+            _1_secs => HistoricalDuration::new(1800, SECOND),
+            _5_secs => HistoricalDuration::new(3600, SECOND),
+            _10_secs | _15_secs => HistoricalDuration::new(14400, SECOND),
+            _30_secs => HistoricalDuration::new(28800, SECOND),
+            _1_min => HistoricalDuration::new(1, DAY),
+            _2_mins => HistoricalDuration::new(2, DAY),
+            _3_mins | _5_mins | _10_mins => HistoricalDuration::new(1, WEEK),
+            _15_mins => HistoricalDuration::new(2, WEEK),
+            _20_mins | _30_mins | _1_hour | _4_hours => HistoricalDuration::new(1, MONTH),
+            _1_day => HistoricalDuration::new(1, YEAR),
+            _1_week | _1_month => HistoricalDuration::new(5, YEAR),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum DurationUnit {
     SECOND, DAY, WEEK, MONTH, YEAR,
 }
 
+/// Duration of a historical data request, sent as e.g. "3 D"
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(into="String", try_from="String")]
+pub struct HistoricalDuration {
+    pub count: u32,
+    pub unit: DurationUnit,
+}
+
+impl HistoricalDuration {
+    pub fn new(count: u32, unit: DurationUnit) -> HistoricalDuration {
+        HistoricalDuration { count, unit }
+    }
+}
+
+impl HistoricalDuration {
+    /// Calendar time covered at most. IB counts days in trading days, so a
+    /// request usually covers less.
+    pub fn max_span(&self) -> chrono::Duration {
+        let seconds = match self.unit {
+            DurationUnit::SECOND => 1,
+            DurationUnit::DAY => 86400,
+            DurationUnit::WEEK => 7 * 86400,
+            DurationUnit::MONTH => 31 * 86400,
+            DurationUnit::YEAR => 366 * 86400,
+        };
+        chrono::Duration::seconds(seconds * self.count as i64)
+    }
+}
+
+impl fmt::Display for HistoricalDuration {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            DurationUnit::SECOND => "S",
+            DurationUnit::DAY => "D",
+            DurationUnit::WEEK => "W",
+            DurationUnit::MONTH => "M",
+            DurationUnit::YEAR => "Y",
+        };
+        write!(fmt, "{} {}", self.count, unit)
+    }
+}
+
+impl From<HistoricalDuration> for String {
+    fn from(duration: HistoricalDuration) -> String {
+        duration.to_string()
+    }
+}
+
+impl TryFrom<String> for HistoricalDuration {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<HistoricalDuration, String> {
+        let (count, unit) = s.split_once(' ').ok_or_else(|| format!("invalid duration {:?}", s))?;
+        let count = count.parse().map_err(|_| format!("invalid duration {:?}", s))?;
+        let unit = match unit {
+            "S" => DurationUnit::SECOND,
+            "D" => DurationUnit::DAY,
+            "W" => DurationUnit::WEEK,
+            "M" => DurationUnit::MONTH,
+            "Y" => DurationUnit::YEAR,
+            _ => return Err(format!("invalid duration {:?}", s)),
+        };
+        Ok(HistoricalDuration { count, unit })
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum DeepType {
@@ -252,8 +344,55 @@ pub enum UsePriceMgmtAlgo {
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct TagValue {
-    tag: String,
-    value: String,
+    pub tag: String,
+    pub value: String,
+}
+
+/// Historical or real-time data bar. `time` is formatted according to
+/// `format_date` of the request, daily and longer bars always use yyyyMMdd and
+/// real-time bars seconds since the Unix epoch.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Bar {
+    pub time: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: i64,
+    pub wap: f64,
+    pub count: i32,
+}
+
+impl Bar {
+    /// Parses `time` as seconds since the Unix epoch (`format_date` 2),
+    /// yyyyMMdd or yyyyMMdd HH:mm:ss. Dates and times without a time zone are
+    /// taken as UTC.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        let time = self.time.trim();
+
+        if time.len() == 8 {
+            let date = NaiveDate::parse_from_str(time, "%Y%m%d").ok()?;
+            return Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?));
+        }
+        if let Ok(seconds) = time.parse::<i64>() {
+            return Utc.timestamp_opt(seconds, 0).single();
+        }
+
+        let time = time.split_whitespace().collect::<Vec<_>>().join(" ");
+        // Drop the time zone, if any
+        let time = NaiveDateTime::parse_from_str(time.get(..17).unwrap_or(&time), "%Y%m%d %H:%M:%S").ok()?;
+        Some(Utc.from_utc_datetime(&time))
+    }
+}
+
+/// Tick of a `ReqTickByTickData` subscription, `time` in seconds since the
+/// Unix epoch
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TickByTick {
+    Last { time: i64, price: f64, size: i64, attr_mask: i32, exchange: String, special_conditions: String },
+    AllLast { time: i64, price: f64, size: i64, attr_mask: i32, exchange: String, special_conditions: String },
+    BidAsk { time: i64, bid_price: f64, ask_price: f64, bid_size: i64, ask_size: i64, attr_mask: i32 },
+    MidPoint { time: i64, mid_point: f64 },
 }
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
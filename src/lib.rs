@@ -1,6 +1,11 @@
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod broadcast;
+pub mod client;
 pub mod ib;
 pub mod protocol;
 pub mod socket;
+pub mod wrapper;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize, Serializer};
+use serde::ser::{SerializeSeq, SerializeTuple};
+
+use crate::ib::{Contract, ContractDetails, Right, TagValue};
+use crate::ib::contract::DeltaNeutralContract;
+use crate::protocol::version::*;
+
+/// Combo legs as sent after the contract in requests such as REQ_MKT_DATA:
+/// the leg count followed by con_id, ratio, action, exchange for each leg.
+///
+/// Nothing is sent unless the contract is a BAG.
+pub(crate) struct BagComboLegs<'a>(pub &'a Contract);
+
+impl<'a> Serialize for BagComboLegs<'a> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if self.0.sec_type != "BAG" {
+            return s.serialize_tuple(0)?.end();
+        }
+
+        let mut seq = s.serialize_seq(Some(self.0.combo_legs.len()))?;
+        for leg in &self.0.combo_legs {
+            seq.serialize_element(&(leg.con_id, leg.ratio, &leg.action, &leg.exchange))?;
+        }
+        seq.end()
+    }
+}
+
+/// A flag followed by con_id, delta, price if the contract has a delta
+/// neutral contract.
+pub(crate) struct DeltaNeutralContractField<'a>(pub &'a Option<DeltaNeutralContract>);
+
+impl<'a> Serialize for DeltaNeutralContractField<'a> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Some(c) => (true, c).serialize(s),
+            None => false.serialize(s),
+        }
+    }
+}
+
+/// CONTRACT_DATA in wire order, also see EDecoder::processContractDataMsg.
+/// Assumes message version 8.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ContractDataMessage {
+    pub(crate) version: i32,
+    pub(crate) req_id: i32,
+    symbol: String,
+    sec_type: String,
+    last_trade_date_or_contract_month: String,
+    strike: f64,
+    right: Right,
+    exchange: String,
+    currency: String,
+    local_symbol: String,
+    market_name: String,
+    trading_class: String,
+    conid: i32,
+    min_tick: f64,
+    md_size_multiplier: Since<i32, MIN_SERVER_VER_MD_SIZE_MULTIPLIER>,
+    multiplier: String,
+    order_types: String,
+    valid_exchanges: String,
+    price_magnifier: i32,
+    under_con_id: i32,
+    long_name: String,
+    primary_exch: String,
+    contract_month: String,
+    industry: String,
+    category: String,
+    subcategory: String,
+    time_zone_id: String,
+    trading_hours: String,
+    liquid_hours: String,
+    ev_rule: String,
+    ev_multiplier: Option<f64>,
+    sec_id_list: Vec<TagValue>,
+    agg_group: Since<i32, MIN_SERVER_VER_AGG_GROUP>,
+    under_symbol: Since<String, MIN_SERVER_VER_UNDERLYING_INFO>,
+    under_sec_type: Since<String, MIN_SERVER_VER_UNDERLYING_INFO>,
+    market_rule_ids: Since<String, MIN_SERVER_VER_MARKET_RULES>,
+    real_expiration_date: Since<String, MIN_SERVER_VER_REAL_EXPIRATION_DATE>,
+    stock_type: Since<String, MIN_SERVER_VER_STOCK_TYPE>,
+}
+
+impl From<ContractDataMessage> for ContractDetails {
+    fn from(m: ContractDataMessage) -> ContractDetails {
+        // Either yyyyMMdd or yyyyMMdd HH:mm
+        let mut last_trade = m.last_trade_date_or_contract_month.splitn(2, [' ', '-']);
+        let last_trade_date_or_contract_month = last_trade.next().unwrap_or_default().to_string();
+        let last_trade_time = last_trade.next().unwrap_or_default().to_string();
+
+        ContractDetails {
+            contract: Contract {
+                conid: m.conid,
+                symbol: m.symbol,
+                sec_type: m.sec_type,
+                last_trade_date_or_contract_month,
+                strike: m.strike,
+                right: m.right,
+                multiplier: m.multiplier,
+                exchange: m.exchange,
+                primary_exch: m.primary_exch,
+                currency: m.currency,
+                local_symbol: m.local_symbol,
+                trading_class: m.trading_class,
+                .. Default::default()
+            },
+            market_name: m.market_name,
+            min_tick: m.min_tick,
+            order_types: m.order_types,
+            valid_exchanges: m.valid_exchanges,
+            price_magnifier: m.price_magnifier,
+            under_con_id: m.under_con_id,
+            long_name: m.long_name,
+            contract_month: m.contract_month,
+            industry: m.industry,
+            category: m.category,
+            subcategory: m.subcategory,
+            time_zone_id: m.time_zone_id,
+            trading_hours: m.trading_hours,
+            liquid_hours: m.liquid_hours,
+            ev_rule: m.ev_rule,
+            ev_multiplier: m.ev_multiplier.unwrap_or_default(),
+            md_size_multiplier: m.md_size_multiplier.into_inner().unwrap_or_default(),
+            agg_group: m.agg_group.into_inner().unwrap_or_default(),
+            under_symbol: m.under_symbol.into_inner().unwrap_or_default(),
+            under_sec_type: m.under_sec_type.into_inner().unwrap_or_default(),
+            market_rule_ids: m.market_rule_ids.into_inner().unwrap_or_default(),
+            real_expiration_date: m.real_expiration_date.into_inner().unwrap_or_default(),
+            last_trade_time,
+            stock_type: m.stock_type.into_inner().unwrap_or_default(),
+            sec_id_list: m.sec_id_list,
+            .. Default::default()
+        }
+    }
+}
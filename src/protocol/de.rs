@@ -1,63 +1,136 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
 use std::io::{BufRead, Cursor};
-use std::io::Error as IoError;
 use std::str::FromStr;
 
 use log::{debug, error};
 use serde::de::Visitor;
-use serde::de::Error as DeError;
-use serde::de::value::Error as SerdeError;
+
+use super::version::{SINCE_TOKEN, UNTIL_TOKEN};
 
 const EOL: u8 = b'\0';
 
-fn convert_io_error(e: IoError) -> SerdeError {
-    SerdeError::custom(e)
+pub type DeserializeResult<T> = Result<T, DecodeError>;
+
+/// Errors while decoding an incoming message. `message_id` is the first field
+/// of the frame and `field_index` counts fields from 0, including the id.
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(std::io::Error),
+    /// The frame ended before the message was fully decoded
+    UnexpectedEof { message_id: String, field_index: usize },
+    /// The message id is not implemented. The rest of the frame was not read.
+    UnknownMessageId(String),
+    /// `field_name` is the path of struct fields leading to the field, e.g.
+    /// `contract.strike`. Empty inside tuples.
+    FieldParse { message_id: String, field_index: usize, field_name: String, raw: String },
+    /// A custom error message from Serde.
+    Custom(String),
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Io(err) => write!(fmt, "IO error: {}", err),
+            DecodeError::UnexpectedEof { message_id, field_index } =>
+                write!(fmt, "Unexpected end of message {} at field {}", message_id, field_index),
+            DecodeError::UnknownMessageId(id) => write!(fmt, "Unknown message id {}", id),
+            DecodeError::FieldParse { message_id, field_index, field_name, raw } =>
+                write!(fmt, "Cannot parse field {} ({}) of message {}: {:?}", field_index, field_name, message_id, raw),
+            DecodeError::Custom(msg) => write!(fmt, "{}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(err: std::io::Error) -> DecodeError {
+        DecodeError::Io(err)
+    }
 }
 
-pub type DeserializeResult<T> = Result<T, SerdeError>;
+impl serde::de::Error for DecodeError {
+    fn custom<T: Display>(desc: T) -> DecodeError {
+        DecodeError::Custom(desc.to_string())
+    }
+}
 
 /// Deserializes IB's API protocol. Each field regardless of type is
 /// represented as a null-terminated string.
+///
+/// `server_version` is the version negotiated in the handshake, used to decide
+/// whether `version::Since` fields are present.
 pub struct Deserializer<R> {
     reader: R,
     peek: Option<Vec<u8>>,
+    server_version: u64,
+    // for error reporting
+    message_id: Option<String>,
+    field_index: usize,
+    path: Vec<&'static str>,
 }
 
 impl<R: BufRead> Deserializer<R> {
-    pub fn new(r: R) -> Deserializer<R> {
+    pub fn new(r: R, server_version: u64) -> Deserializer<R> {
         Deserializer {
             reader: r,
             peek: None,
+            server_version,
+            message_id: None,
+            field_index: 0,
+            path: Vec::new(),
         }
     }
 
-    pub fn new_v100plus(r: &mut R) -> Result<Deserializer<Cursor<Vec<u8>>>, SerdeError> {
-        let mut len = [0; 4];
-
-        r.read_exact(&mut len)
-            .map_err(convert_io_error)?;
+    pub fn server_version(&self) -> u64 {
+        self.server_version
+    }
 
-        let len = u32::from_be_bytes(len);
+    pub fn new_v100plus(r: &mut R, server_version: u64) -> Result<Deserializer<Cursor<Vec<u8>>>, DecodeError> {
+        let buffer = super::read_frame(r)?;
 
-        let mut buffer = vec![0; len as usize];
+        Ok(Deserializer::new(Cursor::new(buffer), server_version))
+    }
 
-        r.read_exact(&mut buffer)
-            .map_err(convert_io_error)?;
+    fn message_id(&self) -> String {
+        self.message_id.clone().unwrap_or_default()
+    }
 
-        Ok(Deserializer::new(Cursor::new(buffer)))
+    /// Error for the last decoded field
+    fn field_error(&self, raw: &[u8]) -> DecodeError {
+        DecodeError::FieldParse {
+            message_id: self.message_id(),
+            field_index: self.field_index.saturating_sub(1),
+            field_name: self.path.join("."),
+            raw: String::from_utf8_lossy(raw).into_owned(),
+        }
     }
 
     fn decode_field(&mut self) -> DeserializeResult<Vec<u8>> {
         let mut buffer = Vec::new();
-        let len = self.reader.read_until(EOL, &mut buffer)
-            .map_err(convert_io_error)?;
+        let len = self.reader.read_until(EOL, &mut buffer)?;
 
         if len == 0 {
-            return Err(SerdeError::custom("EOF"))
+            return Err(DecodeError::UnexpectedEof {
+                message_id: self.message_id(),
+                field_index: self.field_index,
+            })
         }
 
         buffer.pop(); // throw away EOL
 
+        if self.message_id.is_none() {
+            self.message_id = Some(String::from_utf8_lossy(&buffer).into_owned());
+        }
+        self.field_index += 1;
+
         debug!(">>> {:?}", buffer);
         debug!(" >> {}", std::str::from_utf8(&buffer).unwrap_or_default());
 
@@ -80,9 +153,9 @@ impl<R: BufRead> Deserializer<R> {
         };
 
         let parsed = std::str::from_utf8(&buffer)
-            .map_err(SerdeError::custom)?
-            .parse()
-            .map_err(|_| SerdeError::custom(format!("Parse error: expected {}", std::any::type_name::<T>())))?;
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| self.field_error(&buffer))?;
 
         debug!("  > {:?}", parsed);
 
@@ -113,7 +186,7 @@ macro_rules! deserialize_unimplemented {
 }
 
 impl<'de, 'a, R: BufRead> serde::Deserializer<'de> for &'a mut Deserializer<R> {
-    type Error = SerdeError;
+    type Error = DecodeError;
 
     deserialize_parsable!(deserialize_i8, visit_i8);
     deserialize_parsable!(deserialize_i16, visit_i16);
@@ -159,48 +232,17 @@ impl<'de, 'a, R: BufRead> serde::Deserializer<'de> for &'a mut Deserializer<R> {
                              -> DeserializeResult<V::Value>
         where V: Visitor<'de>
     {
-        self.deserialize_tuple(fields.len(), visitor)
+        self.deserialize_fields(fields, visitor)
     }
 
     fn deserialize_tuple<V>(self, len: usize, visitor: V) -> DeserializeResult<V::Value>
     where
         V: Visitor<'de>,
     {
-        struct Access<'a, R: BufRead> {
-            deserializer: &'a mut Deserializer<R>,
-            len: usize,
-        }
-
-        impl<'de, 'a, 'b: 'a, R: BufRead + 'b> serde::de::SeqAccess<'de>
-            for Access<'a, R>
-        {
-            type Error = SerdeError;
-
-            fn next_element_seed<T>(&mut self, seed: T) -> DeserializeResult<Option<T::Value>>
-            where
-                T: serde::de::DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let value = serde::de::DeserializeSeed::deserialize(
-                        seed,
-                        &mut *self.deserializer,
-                    )?;
-                    Ok(Some(value))
-                }
-                else {
-                    Ok(None)
-                }
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
-            }
-        }
-
         visitor.visit_seq(Access {
             deserializer: self,
             len: len,
+            fields: &[],
         })
     }
 
@@ -233,12 +275,19 @@ impl<'de, 'a, R: BufRead> serde::Deserializer<'de> for &'a mut Deserializer<R> {
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> DeserializeResult<V::Value>
     where
         V: Visitor<'de>,
     {
+        if name == SINCE_TOKEN || name == UNTIL_TOKEN {
+            return visitor.visit_seq(SinceAccess {
+                deserializer: self,
+                sent_version: false,
+            });
+        }
+
         visitor.visit_newtype_struct(self)
     }
 
@@ -280,20 +329,113 @@ impl<'de, 'a, R: BufRead> serde::Deserializer<'de> for &'a mut Deserializer<R> {
     deserialize_unimplemented!(deserialize_ignored_any);
 }
 
+impl<'de, R: BufRead> Deserializer<R> {
+    /// Like `deserialize_tuple`, but keeps track of field names for errors
+    fn deserialize_fields<V>(&mut self, fields: &'static [&'static str], visitor: V) -> DeserializeResult<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Access {
+            deserializer: self,
+            len: fields.len(),
+            fields,
+        })
+    }
+}
+
+struct Access<'a, R: BufRead> {
+    deserializer: &'a mut Deserializer<R>,
+    len: usize,
+    /// Empty for tuples
+    fields: &'static [&'static str],
+}
+
+impl<'de, 'a, 'b: 'a, R: BufRead + 'b> serde::de::SeqAccess<'de>
+    for Access<'a, R>
+{
+    type Error = DecodeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> DeserializeResult<Option<T::Value>>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        if self.len > 0 {
+            let field = self.fields.len().checked_sub(self.len)
+                .and_then(|i| self.fields.get(i));
+
+            self.len -= 1;
+
+            if let Some(field) = field {
+                self.deserializer.path.push(field);
+            }
+
+            let value = serde::de::DeserializeSeed::deserialize(
+                seed,
+                &mut *self.deserializer,
+            );
+
+            if field.is_some() {
+                self.deserializer.path.pop();
+            }
+
+            Ok(Some(value?))
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// Yields the server version, then the gated field. The field is only read if
+/// `version::Since` / `version::Until` asks for it.
+struct SinceAccess<'a, R> {
+    deserializer: &'a mut Deserializer<R>,
+    sent_version: bool,
+}
+
+impl<'de, 'a, R: BufRead> serde::de::SeqAccess<'de> for SinceAccess<'a, R> {
+    type Error = DecodeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> DeserializeResult<Option<T::Value>>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        use serde::de::IntoDeserializer;
+
+        if !self.sent_version {
+            self.sent_version = true;
+            let version: u64 = self.deserializer.server_version;
+            return seed.deserialize(version.into_deserializer()).map(Some);
+        }
+
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+}
+
 impl<'de, 'a, R: BufRead + 'a> serde::de::EnumAccess<'de> for &'a mut Deserializer<R>
 {
-    type Error = SerdeError;
+    type Error = DecodeError;
     type Variant = Self;
 
+    /// The first field of a frame is the message id, so failing to match it
+    /// means the message is not implemented.
     fn variant_seed<V>(self, seed: V) -> DeserializeResult<(V::Value, Self::Variant)>
     where
         V: serde::de::DeserializeSeed<'de>,
     {
-        use serde::de::IntoDeserializer;
+        use serde::de::value::StrDeserializer;
 
         let id: String = serde::Deserialize::deserialize(&mut *self)?;
-        let val: DeserializeResult<_> = seed.deserialize(id.into_deserializer());
-        Ok((val?, self))
+
+        match seed.deserialize(StrDeserializer::<DecodeError>::new(&id)) {
+            Ok(val) => Ok((val, self)),
+            Err(_) if self.field_index == 1 => Err(DecodeError::UnknownMessageId(id)),
+            Err(_) => Err(self.field_error(id.as_bytes())),
+        }
     }
 }
 
@@ -301,7 +443,7 @@ impl<'de, 'a, R> serde::de::VariantAccess<'de> for &'a mut Deserializer<R>
 where
     R: BufRead,
 {
-    type Error = SerdeError;
+    type Error = DecodeError;
 
     fn unit_variant(self) -> DeserializeResult<()> {
         Ok(())
@@ -325,6 +467,6 @@ where
     where
         V: Visitor<'de>,
     {
-        serde::de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+        self.deserialize_fields(fields, visitor)
     }
 }
@@ -2,46 +2,83 @@ use std::fmt::Display;
 use std::io::prelude::*;
 
 use serde::{self, Deserialize, Serialize};
-use serde::de::value::Error as DeError;
 
 use self::de::Deserializer;
+pub use self::de::DecodeError;
 use self::ser::Serializer;
 
+pub mod contract;
 pub mod de;
 pub mod ser;
 pub mod order;
+pub mod version;
 
 /// Deserializes directly from a `Buffer`ed Reader.
 ///
-/// If any error occurs, assume the buffer is in an invalid state, as the IB
-/// protocol cannot be re-synchronized.
-pub fn from_reader<'a, R, T>(reader: &'a mut R) -> Result<T, DeError>
+/// The whole frame is read before decoding, so unless an `Io` error occurs,
+/// the reader is positioned at the next frame even if decoding fails.
+///
+/// `server_version` is 0 until the handshake has been read.
+pub fn from_reader<'a, R, T>(reader: &'a mut R, server_version: u64) -> Result<T, DecodeError>
     where R: BufRead,
           T: Deserialize<'a>
 {
-    let mut deserializer = Deserializer::new_v100plus(reader)?;
+    let mut deserializer = Deserializer::new_v100plus(reader, server_version)?;
 
     serde::Deserialize::deserialize(&mut deserializer)
 }
 
+/// Reads one length-prefixed frame, without decoding it.
+pub fn read_frame<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+
+    let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut frame)?;
+
+    Ok(frame)
+}
+
+/// Deserializes a frame read by `read_frame`.
+pub fn from_frame<T>(frame: &[u8], server_version: u64) -> Result<T, DecodeError>
+    where T: for<'a> Deserialize<'a>
+{
+    let mut deserializer = Deserializer::new(frame, server_version);
+
+    serde::Deserialize::deserialize(&mut deserializer)
+}
+
+/// Splits a frame into its raw fields.
+pub fn frame_fields(frame: &[u8]) -> Vec<String> {
+    let frame = frame.strip_suffix(b"\0").unwrap_or(frame);
+
+    if frame.is_empty() {
+        return Vec::new();
+    }
+
+    frame.split(|&b| b == b'\0')
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect()
+}
+
 /// Serializes an object directly into a `Writer`.
 ///
 /// Since we need to prefix with the message length, the serializer will
 /// serialize into a String first.
-pub fn to_writer<W, T: ?Sized>(writer: &mut W, value: &T) -> Result<(), Error>
+pub fn to_writer<W, T: ?Sized>(writer: &mut W, value: &T, server_version: u64) -> Result<(), Error>
 where
     W: std::io::Write,
     T: Serialize,
 {
-    let msg = to_bytes(value)?;
+    let msg = to_bytes(value, server_version)?;
     let len = msg.len() as u32;
     writer.write_all(&len.to_be_bytes())?;
     writer.write_all(&msg)?;
     Ok(())
 }
 
-pub fn to_bytes<T: ?Sized + Serialize>(value: &T) -> Result<Vec<u8>, Error> {
-    let mut serializer = Serializer::new();
+pub fn to_bytes<T: ?Sized + Serialize>(value: &T, server_version: u64) -> Result<Vec<u8>, Error> {
+    let mut serializer = Serializer::new(server_version);
     value.serialize(&mut serializer)?;
     Ok(serializer.output)
 }
@@ -50,6 +87,8 @@ pub fn to_bytes<T: ?Sized + Serialize>(value: &T) -> Result<Vec<u8>, Error> {
 pub enum Error {
     Io(std::io::Error),
     SequenceMustHaveLength,
+    /// The request is not supported by the negotiated server version.
+    UnsupportedVersion { min_version: u64, server_version: u64 },
     /// A custom error message from Serde.
     Custom(String),
 }
@@ -59,6 +98,7 @@ impl std::error::Error for Error {
         match *self {
             Error::Io(ref err) => std::error::Error::description(err),
             Error::SequenceMustHaveLength => "sequence / map must have known length",
+            Error::UnsupportedVersion { .. } => "request not supported by server version",
             Error::Custom(ref msg) => msg,
         }
     }
@@ -67,6 +107,7 @@ impl std::error::Error for Error {
         match *self {
             Error::Io(ref err) => Some(err),
             Error::SequenceMustHaveLength => None,
+            Error::UnsupportedVersion { .. } => None,
             Error::Custom(_) => None,
         }
     }
@@ -83,7 +124,9 @@ impl Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             Error::Io(ref ioerr) => write!(fmt, "IO error: {}", ioerr),
-            Error::SequenceMustHaveLength => write!(fmt, "{}", self),
+            Error::SequenceMustHaveLength => write!(fmt, "sequence / map must have known length"),
+            Error::UnsupportedVersion { min_version, server_version } =>
+                write!(fmt, "request needs server version {}, connected to {}", min_version, server_version),
             Error::Custom(ref s) => s.fmt(fmt),
         }
     }
@@ -1,13 +1,19 @@
-use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
 
 use crate::ib::{Contract, Order, OrderCondition, OrderState};
 use crate::ib::contract::{ComboLeg, DeltaNeutralContract, ShortSaleSlot};
 use crate::ib::message::{OpenOrder};
 use crate::ib::order::{ClearingIntent, OpenClose, OrderComboLeg, OrderType, Origin};
 use crate::ib::types::*;
+use crate::protocol::contract::DeltaNeutralContractField;
+use crate::protocol::version::*;
 
-#[derive(Deserialize)]
 pub struct OpenOrderMessage {
+    _version: Until<i32, MIN_SERVER_VER_ORDER_CONTAINER>,
     order_id: i32,
     contract: ContractFields,
     order: OrderBasic,
@@ -19,7 +25,7 @@ pub struct OpenOrderMessage {
     good_after_time: String,
     _shares_allocation: String, // deprecated
     fa_params: FAParams,
-    model_code: String,
+    model_code: Since<String, MIN_SERVER_VER_MODELS_SUPPORT>,
     good_till_date: String,
     rule80a: Rule80A,
     percent_offset: Option<f64>,
@@ -45,6 +51,8 @@ pub struct OpenOrderMessage {
     combo_legs: ComboLegs,
     smart_combo_routing_params: Vec<TagValue>,
     scale_order_params: ScaleOrderParams,
+    /// Only if `scale_price_increment` > 0
+    scale_price_params: Option<ScalePriceParams>,
     hedge_type: HedgeType,
     opt_out_smart_routing: bool,
     clearing_params: ClearingParams,
@@ -54,15 +62,217 @@ pub struct OpenOrderMessage {
     solicited: bool,
     what_if_info_and_commission: WhatIfInfoAndCommission,
     vol_randomize_flags: VolRandomizeFlags,
-    // TODO only if PEG_BENCH: peg_to_bench_params: PegToBenchParams,
-    conditions: Conditions,
-    adjusted_order_params: AdjustedOrderParams,
-    soft_dollar_tier: SoftDollarTier,
-    cash_qty: Option<f64>,
-    dont_use_auto_price_for_hedge: bool,
-    is_oms_container: bool,
-    discretionary_up_to_limit_price: bool,
-    use_price_mgmt_algo: Option<bool>,
+    /// Only if `order_type` is PEG_BENCH
+    peg_to_bench_params: Option<PegToBenchParams>,
+    conditions: Since<Vec<OrderCondition>, MIN_SERVER_VER_PEGGED_TO_BENCHMARK>,
+    /// Only if there are `conditions`
+    conditions_flags: Option<ConditionsFlags>,
+    adjusted_order_params: Since<AdjustedOrderParams, MIN_SERVER_VER_PEGGED_TO_BENCHMARK>,
+    soft_dollar_tier: Since<SoftDollarTier, MIN_SERVER_VER_SOFT_DOLLAR_TIER>,
+    cash_qty: Since<Option<f64>, MIN_SERVER_VER_CASH_QTY>,
+    dont_use_auto_price_for_hedge: Since<bool, MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE>,
+    is_oms_container: Since<bool, MIN_SERVER_VER_ORDER_CONTAINER>,
+    discretionary_up_to_limit_price: Since<bool, MIN_SERVER_VER_D_PEG_ORDERS>,
+    use_price_mgmt_algo: Since<Option<bool>, MIN_SERVER_VER_PRICE_MGMT_ALGO>,
+}
+
+/// Reads `T` only if the condition is true, otherwise `None` without
+/// consuming any input.
+struct If<T>(bool, PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for If<T> {
+    type Value = Option<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        if self.0 {
+            T::deserialize(deserializer).map(Some)
+        }
+        else {
+            Ok(None)
+        }
+    }
+}
+
+/// Like `If`, but also only read since server version `V`, see `Since`.
+struct SinceIf<T, const V: u64>(bool, PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>, const V: u64> DeserializeSeed<'de> for SinceIf<T, V> {
+    type Value = Option<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        if self.0 {
+            Since::<T, V>::deserialize(deserializer).map(Since::into_inner)
+        }
+        else {
+            Ok(None)
+        }
+    }
+}
+
+/// Implemented by hand because some blocks are only sent depending on values
+/// read earlier in the message.
+impl<'de> Deserialize<'de> for OpenOrderMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OpenOrderVisitor;
+
+        impl<'de> Visitor<'de> for OpenOrderVisitor {
+            type Value = OpenOrderMessage;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct OpenOrderMessage")
+            }
+
+            fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<Self::Value, V::Error> {
+                let mut index = 0;
+
+                // Conditional blocks use `next!(if cond)` so they still take up
+                // their slot in FIELDS, keeping field names in errors aligned.
+                macro_rules! next {
+                    () => {{
+                        index += 1;
+                        seq.next_element()?
+                            .ok_or_else(|| serde::de::Error::invalid_length(index - 1, &self))?
+                    }};
+                    (if $cond:expr) => {{
+                        index += 1;
+                        seq.next_element_seed(If($cond, PhantomData))?
+                            .ok_or_else(|| serde::de::Error::invalid_length(index - 1, &self))?
+                    }};
+                    (since $version:ident if $cond:expr) => {{
+                        index += 1;
+                        seq.next_element_seed(SinceIf::<_, $version>($cond, PhantomData))?
+                            .ok_or_else(|| serde::de::Error::invalid_length(index - 1, &self))?
+                    }};
+                }
+
+                let _version = next!();
+                let order_id = next!();
+                let contract = next!();
+                let order: OrderBasic = next!();
+                let client_id = next!();
+                let perm_id = next!();
+                let outside_rth = next!();
+                let hidden = next!();
+                let discretionary_amt = next!();
+                let good_after_time = next!();
+                let _shares_allocation = next!();
+                let fa_params = next!();
+                let model_code = next!();
+                let good_till_date = next!();
+                let rule80a = next!();
+                let percent_offset = next!();
+                let settling_firm = next!();
+                let short_sale_params = next!();
+                let auction_strategy = next!();
+                let box_order_params = next!();
+                let peg_to_stk_or_vol_order_params = next!();
+                let display_size = next!();
+                let block_order = next!();
+                let sweep_to_fill = next!();
+                let all_or_none = next!();
+                let min_qty = next!();
+                let oca_type = next!();
+                let e_trade_only = next!();
+                let firm_quote_only = next!();
+                let nbbo_price_cap = next!();
+                let parent_id = next!();
+                let trigger_method = next!();
+                let vol_order_params = next!();
+                let trail_params = next!();
+                let basis_points = next!();
+                let combo_legs = next!();
+                let smart_combo_routing_params = next!();
+                let scale_order_params: ScaleOrderParams = next!();
+                let scale_price_params = next!(if scale_order_params.has_price_params());
+                let hedge_type = next!();
+                let opt_out_smart_routing = next!();
+                let clearing_params = next!();
+                let not_held = next!();
+                let c_delta_neutral = next!();
+                let algo = next!();
+                let solicited = next!();
+                let what_if_info_and_commission = next!();
+                let vol_randomize_flags = next!();
+                let peg_to_bench_params = next!(since MIN_SERVER_VER_PEGGED_TO_BENCHMARK if order.order_type == OrderType::PEG_BENCH);
+                let conditions: Since<Vec<OrderCondition>, MIN_SERVER_VER_PEGGED_TO_BENCHMARK> = next!();
+                let conditions_flags = next!(if matches!(conditions.0, Some(ref c) if !c.is_empty()));
+                let adjusted_order_params = next!();
+                let soft_dollar_tier = next!();
+                let cash_qty = next!();
+                let dont_use_auto_price_for_hedge = next!();
+                let is_oms_container = next!();
+                let discretionary_up_to_limit_price = next!();
+                let use_price_mgmt_algo = next!();
+
+                Ok(OpenOrderMessage {
+                    _version,
+                    order_id,
+                    contract,
+                    order,
+                    client_id,
+                    perm_id,
+                    outside_rth,
+                    hidden,
+                    discretionary_amt,
+                    good_after_time,
+                    _shares_allocation,
+                    fa_params,
+                    model_code,
+                    good_till_date,
+                    rule80a,
+                    percent_offset,
+                    settling_firm,
+                    short_sale_params,
+                    auction_strategy,
+                    box_order_params,
+                    peg_to_stk_or_vol_order_params,
+                    display_size,
+                    block_order,
+                    sweep_to_fill,
+                    all_or_none,
+                    min_qty,
+                    oca_type,
+                    e_trade_only,
+                    firm_quote_only,
+                    nbbo_price_cap,
+                    parent_id,
+                    trigger_method,
+                    vol_order_params,
+                    trail_params,
+                    basis_points,
+                    combo_legs,
+                    smart_combo_routing_params,
+                    scale_order_params,
+                    scale_price_params,
+                    hedge_type,
+                    opt_out_smart_routing,
+                    clearing_params,
+                    not_held,
+                    c_delta_neutral,
+                    algo,
+                    solicited,
+                    what_if_info_and_commission,
+                    vol_randomize_flags,
+                    peg_to_bench_params,
+                    conditions,
+                    conditions_flags,
+                    adjusted_order_params,
+                    soft_dollar_tier,
+                    cash_qty,
+                    dont_use_auto_price_for_hedge,
+                    is_oms_container,
+                    discretionary_up_to_limit_price,
+                    use_price_mgmt_algo,
+                })
+            }
+        }
+
+        const FIELDS: &[&str] = &[
+            "_version", "order_id", "contract", "order", "client_id", "perm_id", "outside_rth", "hidden", "discretionary_amt", "good_after_time", "_shares_allocation", "fa_params", "model_code", "good_till_date", "rule80a", "percent_offset", "settling_firm", "short_sale_params", "auction_strategy", "box_order_params", "peg_to_stk_or_vol_order_params", "display_size", "block_order", "sweep_to_fill", "all_or_none", "min_qty", "oca_type", "e_trade_only", "firm_quote_only", "nbbo_price_cap", "parent_id", "trigger_method", "vol_order_params", "trail_params", "basis_points", "combo_legs", "smart_combo_routing_params", "scale_order_params", "scale_price_params", "hedge_type", "opt_out_smart_routing", "clearing_params", "not_held", "c_delta_neutral", "algo", "solicited", "what_if_info_and_commission", "vol_randomize_flags", "peg_to_bench_params", "conditions", "conditions_flags", "adjusted_order_params", "soft_dollar_tier", "cash_qty", "dont_use_auto_price_for_hedge", "is_oms_container", "discretionary_up_to_limit_price", "use_price_mgmt_algo"
+        ];
+
+        deserializer.deserialize_struct("OpenOrderMessage", FIELDS, OpenOrderVisitor)
+    }
 }
 
 impl From<OpenOrderMessage> for OpenOrder {
@@ -105,7 +315,7 @@ impl From<OpenOrderMessage> for OpenOrder {
         order.fa_method = f.fa_params.method;
         order.fa_percentage = f.fa_params.percentage;
         order.fa_profile = f.fa_params.profile;
-        order.model_code = f.model_code;
+        order.model_code = f.model_code.0.unwrap_or_default();
         order.good_till_date = f.good_till_date;
         order.rule80a = f.rule80a;
         order.percent_offset = f.percent_offset;
@@ -158,7 +368,16 @@ impl From<OpenOrderMessage> for OpenOrder {
         let params = f.scale_order_params;
         order.scale_init_level_size = params.scale_init_level_size;
         order.scale_subs_level_size = params.scale_subs_level_size;
-        // TODO order.scale_price_increment = params.scale_price_increment;
+        order.scale_price_increment = params.scale_price_increment;
+        if let Some(params) = f.scale_price_params {
+            order.scale_price_adjust_value = params.scale_price_adjust_value;
+            order.scale_price_adjust_interval = params.scale_price_adjust_interval;
+            order.scale_profit_offset = params.scale_profit_offset;
+            order.scale_auto_reset = params.scale_auto_reset;
+            order.scale_init_position = params.scale_init_position;
+            order.scale_init_fill_qty = params.scale_init_fill_qty;
+            order.scale_random_percent = params.scale_random_percent;
+        }
         order.hedge_type = f.hedge_type;
         order.opt_out_smart_routing = f.opt_out_smart_routing;
         let params = f.clearing_params;
@@ -176,12 +395,13 @@ impl From<OpenOrderMessage> for OpenOrder {
         let comm = f.what_if_info_and_commission;
         order.what_if = comm.what_if;
         state.status = comm.s_status;
-        state.init_margin_before = comm.s_init_margin_before;
-        state.maint_margin_before = comm.s_maint_margin_before;
-        state.equity_with_loan_before = comm.s_equity_with_loan_before;
-        state.init_margin_change = comm.s_init_margin_change;
-        state.maint_margin_change = comm.s_maint_margin_change;
-        state.equity_with_loan_change = comm.s_equity_with_loan_change;
+        let margin = comm.s_before_and_change.0.unwrap_or_default();
+        state.init_margin_before = margin.s_init_margin_before;
+        state.maint_margin_before = margin.s_maint_margin_before;
+        state.equity_with_loan_before = margin.s_equity_with_loan_before;
+        state.init_margin_change = margin.s_init_margin_change;
+        state.maint_margin_change = margin.s_maint_margin_change;
+        state.equity_with_loan_change = margin.s_equity_with_loan_change;
         state.init_margin_after = comm.s_init_margin_after;
         state.maint_margin_after = comm.s_maint_margin_after;
         state.equity_with_loan_after = comm.s_equity_with_loan_after;
@@ -193,32 +413,383 @@ impl From<OpenOrderMessage> for OpenOrder {
         let flags = f.vol_randomize_flags;
         order.randomize_size = flags.randomize_size;
         order.randomize_price = flags.randomize_price;
-        // TODO only if PEG_BENCH: peg_to_bench_params: PegToBenchParams
-        let conditions = f.conditions;
-        order.conditions = conditions.conditions;
-        // TODO if conditions.len() > 0
-        //   conditionsIgnoreRth
-        //   conditionsCancelOrder
-        let params = f.adjusted_order_params;
-        order.adjusted_order_type = params.adjusted_order_type;
-        order.trigger_price = params.trigger_price;
-        order.trail_stop_price = params.stop_price_and_lmt_price_offset.trail_stop_price;
-        order.lmt_price_offset = params.stop_price_and_lmt_price_offset.lmt_price_offset;
-        order.adjusted_stop_price = params.adjusted_stop_price;
-        order.adjusted_stop_limit_price = params.adjusted_stop_limit_price;
-        order.adjusted_trailing_amount = params.adjusted_trailing_amount;
-        order.adjustable_trailing_unit = params.adjustable_trailing_unit;
-        order.soft_dollar_tier = f.soft_dollar_tier;
-        order.cash_qty = f.cash_qty;
-        order.dont_use_auto_price_for_hedge = f.dont_use_auto_price_for_hedge;
-        order.is_oms_container = f.is_oms_container;
-        order.discretionary_up_to_limit_price = f.discretionary_up_to_limit_price;
-        order.use_price_mgmt_algo = f.use_price_mgmt_algo;
+        if let Some(params) = f.peg_to_bench_params {
+            order.reference_contract_id = params.reference_contract_id;
+            order.is_pegged_change_amount_decrease = params.is_pegged_change_amount_decrease;
+            order.pegged_change_amount = params.pegged_change_amount;
+            order.reference_change_amount = params.reference_change_amount;
+            order.reference_exchange_id = params.reference_exchange_id;
+        }
+        order.conditions = f.conditions.0.unwrap_or_default();
+        if let Some(flags) = f.conditions_flags {
+            order.conditions_ignore_rth = flags.conditions_ignore_rth;
+            order.conditions_cancel_order = flags.conditions_cancel_order;
+        }
+        if let Some(params) = f.adjusted_order_params.0 {
+            order.adjusted_order_type = params.adjusted_order_type;
+            order.trigger_price = params.trigger_price;
+            order.trail_stop_price = params.stop_price_and_lmt_price_offset.trail_stop_price;
+            order.lmt_price_offset = params.stop_price_and_lmt_price_offset.lmt_price_offset;
+            order.adjusted_stop_price = params.adjusted_stop_price;
+            order.adjusted_stop_limit_price = params.adjusted_stop_limit_price;
+            order.adjusted_trailing_amount = params.adjusted_trailing_amount;
+            order.adjustable_trailing_unit = params.adjustable_trailing_unit;
+        }
+        order.soft_dollar_tier = f.soft_dollar_tier.0.unwrap_or_default();
+        order.cash_qty = f.cash_qty.0.flatten();
+        order.dont_use_auto_price_for_hedge = f.dont_use_auto_price_for_hedge.0.unwrap_or_default();
+        order.is_oms_container = f.is_oms_container.0.unwrap_or_default();
+        order.discretionary_up_to_limit_price = f.discretionary_up_to_limit_price.0.unwrap_or_default();
+        order.use_price_mgmt_algo = f.use_price_mgmt_algo.0.flatten();
 
         OpenOrder { contract, order, state }
     }
 }
 
+/// Field layout of PLACE_ORDER, see EClient::placeOrder.
+///
+/// The message version is not sent, so this requires server version
+/// MIN_SERVER_VER_ORDER_CONTAINER or later. Encoding fails with
+/// `Error::UnsupportedVersion` for older servers.
+#[derive(Serialize)]
+pub(crate) struct PlaceOrderMessage<'a> {
+    _requires: Requires<MIN_SERVER_VER_ORDER_CONTAINER>,
+    order_id: i32,
+    contract: &'a Contract,
+    sec_id_type: &'a str,
+    sec_id: &'a str,
+
+    action: &'a Action,
+    total_quantity: f64,
+    order_type: &'a OrderType,
+    lmt_price: Option<f64>,
+    aux_price: Option<f64>,
+
+    tif: &'a TimeInForce,
+    oca_group: &'a str,
+    account: &'a str,
+    open_close: &'a OpenClose,
+    origin: &'a Origin,
+    order_ref: &'a str,
+    transmit: bool,
+    parent_id: i32,
+    block_order: bool,
+    sweep_to_fill: bool,
+    display_size: i32,
+    trigger_method: &'a TriggerMethod,
+    outside_rth: bool,
+    hidden: bool,
+
+    /// Only for BAG contracts
+    #[serde(skip_serializing_if="Option::is_none")]
+    combo_legs: Option<BagLegs<'a>>,
+
+    _shares_allocation: &'a str, // deprecated
+    discretionary_amt: f64,
+    good_after_time: &'a str,
+    good_till_date: &'a str,
+    fa_group: &'a str,
+    fa_method: &'a str,
+    fa_percentage: &'a str,
+    fa_profile: &'a str,
+    model_code: &'a str,
+    short_sale_slot: &'a ShortSaleSlot,
+    designated_location: &'a str,
+    exempt_code: i32,
+    oca_type: &'a OcaType,
+    rule80a: &'a Rule80A,
+    settling_firm: &'a str,
+    all_or_none: bool,
+    min_qty: Option<i32>,
+    percent_offset: Option<f64>,
+    e_trade_only: bool,
+    firm_quote_only: bool,
+    nbbo_price_cap: Option<f64>,
+    auction_strategy: i32,
+    starting_price: Option<f64>,
+    stock_ref_price: Option<f64>,
+    delta: Option<f64>,
+    stock_range_lower: Option<f64>,
+    stock_range_upper: Option<f64>,
+    override_percentage_constraints: bool,
+
+    volatility: Option<f64>,
+    volatility_type: Option<&'a VolatilityType>,
+    delta_neutral_order_type: &'a str,
+    delta_neutral_aux_price: Option<f64>,
+    /// Only if `delta_neutral_order_type` is set
+    #[serde(skip_serializing_if="Option::is_none")]
+    delta_neutral_params: Option<DeltaNeutralOrderParams<'a>>,
+    continuous_update: bool,
+    reference_price_type: Option<&'a ReferencePriceType>,
+    trail_stop_price: Option<f64>,
+    trailing_percent: Option<f64>,
+
+    scale_init_level_size: Option<i32>,
+    scale_subs_level_size: Option<i32>,
+    scale_price_increment: Option<f64>,
+    /// Only if `scale_price_increment` > 0
+    #[serde(skip_serializing_if="Option::is_none")]
+    scale_price_params: Option<ScalePriceParams>,
+    scale_table: &'a str,
+    active_start_time: &'a str,
+    active_stop_time: &'a str,
+
+    /// Includes `hedge_param` if not None
+    hedge_type: &'a HedgeType,
+    opt_out_smart_routing: bool,
+    clearing_account: &'a str,
+    clearing_intent: &'a ClearingIntent,
+    not_held: bool,
+    delta_neutral_contract: DeltaNeutralContractField<'a>,
+
+    algo_strategy: &'a str,
+    /// Only if `algo_strategy` is set
+    #[serde(skip_serializing_if="Option::is_none")]
+    algo_params: Option<&'a Vec<TagValue>>,
+    algo_id: &'a str,
+    what_if: bool,
+    order_misc_options: String,
+    solicited: bool,
+    randomize_size: bool,
+    randomize_price: bool,
+
+    /// Only if `order_type` is PEG_BENCH
+    #[serde(skip_serializing_if="Option::is_none")]
+    peg_to_bench_params: Option<PegToBenchParams>,
+    conditions: &'a Vec<OrderCondition>,
+    /// Only if there are `conditions`
+    #[serde(skip_serializing_if="Option::is_none")]
+    conditions_flags: Option<ConditionsFlags>,
+    adjusted_order_type: &'a str,
+    trigger_price: Option<f64>,
+    lmt_price_offset: Option<f64>,
+    adjusted_stop_price: Option<f64>,
+    adjusted_stop_limit_price: Option<f64>,
+    adjusted_trailing_amount: Option<f64>,
+    adjustable_trailing_unit: i32,
+    ext_operator: &'a str,
+    soft_dollar_tier_name: &'a str,
+    soft_dollar_tier_value: &'a str,
+    cash_qty: Option<f64>,
+    mifid2_decision_maker: &'a str,
+    mifid2_decision_algo: &'a str,
+    mifid2_execution_trader: &'a str,
+    mifid2_execution_algo: &'a str,
+    dont_use_auto_price_for_hedge: Since<bool, MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE>,
+    is_oms_container: Since<bool, MIN_SERVER_VER_ORDER_CONTAINER>,
+    discretionary_up_to_limit_price: Since<bool, MIN_SERVER_VER_D_PEG_ORDERS>,
+    use_price_mgmt_algo: Since<Option<bool>, MIN_SERVER_VER_PRICE_MGMT_ALGO>,
+}
+
+impl<'a> PlaceOrderMessage<'a> {
+    pub(crate) fn new(order_id: i32, contract: &'a Contract, order: &'a Order) -> PlaceOrderMessage<'a> {
+        let is_bag = contract.sec_type == "BAG";
+
+        let has_delta_neutral_order_type = !order.delta_neutral_order_type.is_empty()
+            && order.delta_neutral_order_type != "None";
+
+        PlaceOrderMessage {
+            _requires: Requires,
+            order_id,
+            contract,
+            sec_id_type: &contract.sec_id_type,
+            sec_id: &contract.sec_id,
+
+            action: &order.action,
+            total_quantity: order.total_quantity,
+            order_type: &order.order_type,
+            lmt_price: order.lmt_price,
+            aux_price: order.aux_price,
+
+            tif: &order.tif,
+            oca_group: &order.oca_group,
+            account: &order.account,
+            open_close: &order.open_close,
+            origin: &order.origin,
+            order_ref: &order.order_ref,
+            transmit: order.transmit,
+            parent_id: order.parent_id,
+            block_order: order.block_order,
+            sweep_to_fill: order.sweep_to_fill,
+            display_size: order.display_size,
+            trigger_method: &order.trigger_method,
+            outside_rth: order.outside_rth,
+            hidden: order.hidden,
+
+            combo_legs: if is_bag {
+                Some(BagLegs {
+                    combo_legs: &contract.combo_legs,
+                    order_combo_legs: &order.order_combo_legs,
+                    smart_combo_routing_params: &order.smart_combo_routing_params,
+                })
+            }
+            else {
+                None
+            },
+
+            _shares_allocation: "",
+            discretionary_amt: order.discretionary_amt,
+            good_after_time: &order.good_after_time,
+            good_till_date: &order.good_till_date,
+            fa_group: &order.fa_group,
+            fa_method: &order.fa_method,
+            fa_percentage: &order.fa_percentage,
+            fa_profile: &order.fa_profile,
+            model_code: &order.model_code,
+            short_sale_slot: &order.short_sale_slot,
+            designated_location: &order.designated_location,
+            exempt_code: order.exempt_code,
+            oca_type: &order.oca_type,
+            rule80a: &order.rule80a,
+            settling_firm: &order.settling_firm,
+            all_or_none: order.all_or_none,
+            min_qty: order.min_qty,
+            percent_offset: order.percent_offset,
+            e_trade_only: order.e_trade_only,
+            firm_quote_only: order.firm_quote_only,
+            nbbo_price_cap: order.nbbo_price_cap,
+            auction_strategy: order.auction_strategy.unwrap_or_default(),
+            starting_price: order.starting_price,
+            stock_ref_price: order.stock_ref_price,
+            delta: order.delta,
+            stock_range_lower: order.stock_range_lower,
+            stock_range_upper: order.stock_range_upper,
+            override_percentage_constraints: order.override_percentage_constraints,
+
+            volatility: order.volatility,
+            volatility_type: match order.volatility_type {
+                VolatilityType::None => None,
+                ref t => Some(t),
+            },
+            delta_neutral_order_type: if has_delta_neutral_order_type { &order.delta_neutral_order_type } else { "" },
+            delta_neutral_aux_price: order.delta_neutral_aux_price,
+            delta_neutral_params: if has_delta_neutral_order_type {
+                Some(DeltaNeutralOrderParams {
+                    con_id: order.delta_neutral_con_id,
+                    settling_firm: &order.delta_neutral_settling_firm,
+                    clearing_account: &order.delta_neutral_clearing_account,
+                    clearing_intent: &order.delta_neutral_clearing_intent,
+                    open_close: &order.delta_neutral_open_close,
+                    short_sale: order.delta_neutral_short_sale.unwrap_or_default(),
+                    short_sale_slot: &order.delta_neutral_short_sale_slot,
+                    designated_location: &order.delta_neutral_designated_location,
+                })
+            }
+            else {
+                None
+            },
+            continuous_update: order.continuous_update,
+            reference_price_type: match order.reference_price_type {
+                ReferencePriceType::None => None,
+                ref t => Some(t),
+            },
+            trail_stop_price: order.trail_stop_price,
+            trailing_percent: order.trailing_percent,
+
+            scale_init_level_size: order.scale_init_level_size,
+            scale_subs_level_size: order.scale_subs_level_size,
+            scale_price_increment: order.scale_price_increment,
+            scale_price_params: if matches!(order.scale_price_increment, Some(i) if i > 0.0) {
+                Some(ScalePriceParams {
+                    scale_price_adjust_value: order.scale_price_adjust_value,
+                    scale_price_adjust_interval: order.scale_price_adjust_interval,
+                    scale_profit_offset: order.scale_profit_offset,
+                    scale_auto_reset: order.scale_auto_reset,
+                    scale_init_position: order.scale_init_position,
+                    scale_init_fill_qty: order.scale_init_fill_qty,
+                    scale_random_percent: order.scale_random_percent,
+                })
+            }
+            else {
+                None
+            },
+            scale_table: &order.scale_table,
+            active_start_time: &order.active_start_time,
+            active_stop_time: &order.active_stop_time,
+
+            hedge_type: &order.hedge_type,
+            opt_out_smart_routing: order.opt_out_smart_routing,
+            clearing_account: &order.clearing_account,
+            clearing_intent: &order.clearing_intent,
+            not_held: order.not_held,
+            delta_neutral_contract: DeltaNeutralContractField(&contract.delta_neutral_contract),
+
+            algo_strategy: &order.algo_strategy,
+            algo_params: if order.algo_strategy.is_empty() { None } else { Some(&order.algo_params) },
+            algo_id: &order.algo_id,
+            what_if: order.what_if,
+            order_misc_options: order.order_misc_options.iter()
+                .map(|tv| format!("{}={};", tv.tag, tv.value))
+                .collect(),
+            solicited: order.solicited,
+            randomize_size: order.randomize_size,
+            randomize_price: order.randomize_price,
+
+            peg_to_bench_params: if order.order_type == OrderType::PEG_BENCH {
+                Some(PegToBenchParams {
+                    reference_contract_id: order.reference_contract_id,
+                    is_pegged_change_amount_decrease: order.is_pegged_change_amount_decrease,
+                    pegged_change_amount: order.pegged_change_amount,
+                    reference_change_amount: order.reference_change_amount,
+                    reference_exchange_id: order.reference_exchange_id.clone(),
+                })
+            }
+            else {
+                None
+            },
+            conditions: &order.conditions,
+            conditions_flags: if !order.conditions.is_empty() {
+                Some(ConditionsFlags {
+                    conditions_ignore_rth: order.conditions_ignore_rth,
+                    conditions_cancel_order: order.conditions_cancel_order,
+                })
+            }
+            else {
+                None
+            },
+            adjusted_order_type: &order.adjusted_order_type,
+            trigger_price: order.trigger_price,
+            lmt_price_offset: order.lmt_price_offset,
+            adjusted_stop_price: order.adjusted_stop_price,
+            adjusted_stop_limit_price: order.adjusted_stop_limit_price,
+            adjusted_trailing_amount: order.adjusted_trailing_amount,
+            adjustable_trailing_unit: order.adjustable_trailing_unit,
+            ext_operator: &order.ext_operator,
+            soft_dollar_tier_name: &order.soft_dollar_tier.name,
+            soft_dollar_tier_value: &order.soft_dollar_tier.value,
+            cash_qty: order.cash_qty,
+            mifid2_decision_maker: &order.mifid2_decision_maker,
+            mifid2_decision_algo: &order.mifid2_decision_algo,
+            mifid2_execution_trader: &order.mifid2_execution_trader,
+            mifid2_execution_algo: &order.mifid2_execution_algo,
+            dont_use_auto_price_for_hedge: order.dont_use_auto_price_for_hedge.into(),
+            is_oms_container: order.is_oms_container.into(),
+            discretionary_up_to_limit_price: order.discretionary_up_to_limit_price.into(),
+            use_price_mgmt_algo: order.use_price_mgmt_algo.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BagLegs<'a> {
+    combo_legs: &'a Vec<ComboLeg>,
+    order_combo_legs: &'a Vec<OrderComboLeg>,
+    smart_combo_routing_params: &'a Vec<TagValue>,
+}
+
+#[derive(Serialize)]
+struct DeltaNeutralOrderParams<'a> {
+    con_id: i32,
+    settling_firm: &'a str,
+    clearing_account: &'a str,
+    clearing_intent: &'a ClearingIntent,
+    open_close: &'a OpenClose,
+    short_sale: bool,
+    short_sale_slot: &'a ShortSaleSlot,
+    designated_location: &'a str,
+}
+
 #[derive(Deserialize)]
 struct ContractFields {
     conid: i32,
@@ -337,18 +908,25 @@ struct ComboLegs {
 struct ScaleOrderParams {
     scale_init_level_size: Option<i32>,
     scale_subs_level_size: Option<i32>,
-    _scale_price_increment: EmptyString, // TODO Option<f64>,
+    scale_price_increment: Option<f64>,
+}
 
-    /* TODO: requires manual deserialize implementation
-    if scalePriceIncrement > 0.0 && scalePriceIncrement != Double.MAX_VALUE {
-        scalePriceAdjustValue: Option<f64>,
-        scalePriceAdjustInterval: Option<i32>,
-        scaleProfitOffset: Option<f64>,
-        scaleAutoReset: bool,
-        scaleInitPosition: Option<i32>,
-        scaleInitFillQty: Option<i32>,
-        scaleRandomPercent: bool,
-    } */
+impl ScaleOrderParams {
+    /// f64::MAX is already decoded as None
+    fn has_price_params(&self) -> bool {
+        matches!(self.scale_price_increment, Some(i) if i > 0.0)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ScalePriceParams {
+    scale_price_adjust_value: Option<f64>,
+    scale_price_adjust_interval: Option<i32>,
+    scale_profit_offset: Option<f64>,
+    scale_auto_reset: bool,
+    scale_init_position: Option<i32>,
+    scale_init_fill_qty: Option<i32>,
+    scale_random_percent: bool,
 }
 
 #[derive(Deserialize)]
@@ -367,14 +945,7 @@ enum DeltaNeutral {
 struct WhatIfInfoAndCommission {
     what_if: bool,
     s_status: String,
-
-    s_init_margin_before: String,
-    s_maint_margin_before: String,
-    s_equity_with_loan_before: String,
-    s_init_margin_change: String,
-    s_maint_margin_change: String,
-    s_equity_with_loan_change: String,
-
+    s_before_and_change: Since<MarginBeforeAndChange, MIN_SERVER_VER_WHAT_IF_EXT_FIELDS>,
     s_init_margin_after: String,
     s_maint_margin_after: String,
     s_equity_with_loan_after: String,
@@ -385,15 +956,23 @@ struct WhatIfInfoAndCommission {
     s_warning_text: String,
 }
 
+#[derive(Default, Deserialize)]
+struct MarginBeforeAndChange {
+    s_init_margin_before: String,
+    s_maint_margin_before: String,
+    s_equity_with_loan_before: String,
+    s_init_margin_change: String,
+    s_maint_margin_change: String,
+    s_equity_with_loan_change: String,
+}
+
 #[derive(Deserialize)]
 struct VolRandomizeFlags {
     randomize_size: bool,
     randomize_price: bool,
 }
 
-// TODO: order_type == OrderType.PEG_BENCH
-#[allow(dead_code)]
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct PegToBenchParams {
     reference_contract_id: i32,
     is_pegged_change_amount_decrease: bool,
@@ -402,12 +981,10 @@ struct PegToBenchParams {
     reference_exchange_id: String,
 }
 
-#[derive(Deserialize)]
-struct Conditions {
-    conditions: Vec<OrderCondition>,
-    // TODO if conditions.len() > 0 {
-    //conditionsIgnoreRth: bool,
-    //conditionsCancelOrder: bool,
+#[derive(Deserialize, Serialize)]
+struct ConditionsFlags {
+    conditions_ignore_rth: bool,
+    conditions_cancel_order: bool,
 }
 
 #[derive(Deserialize)]
@@ -427,11 +1004,6 @@ struct StopPriceAndLmtPriceOffset {
     lmt_price_offset: Option<f64>,
 }
 
-#[derive(Deserialize)]
-enum EmptyString {
-    #[serde(rename="")] None,
-}
-
 /// If string is empty, don't read any other fields.
 /// Otherwise, keep string and read type
 enum OptionalType<T> {
@@ -481,3 +1053,346 @@ impl<'de, T: Deserialize<'de>> Deserialize<'de> for OptionalType<T> {
         deserializer.deserialize_struct("OptionalType", FIELDS, OptionalTypeVisitor { marker: PhantomData, m: PhantomData })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::ib::{Contract, Message, OpenOrder, Order, OrderCondition, Request};
+    use crate::ib::contract::{ComboLeg, DeltaNeutralContract};
+    use crate::ib::order::{OrderComboLeg, OrderType};
+    use crate::ib::order_condition::AndOr;
+    use crate::ib::types::{Action, OcaType, TagValue, TriggerMethod};
+    use crate::protocol::{self, Error};
+    use crate::protocol::version::*;
+
+    const SERVER_VERSION: u64 = MIN_SERVER_VER_PRICE_MGMT_ALGO;
+
+    /// PLACE_ORDER of `lmt_order`, in the order EClient::placeOrder writes it
+    const PLACE_ORDER: &[&str] = &[
+        "3", "7",
+        // contract, sec id type, sec id
+        "0", "AAPL", "STK", "", "0", "", "", "SMART", "", "USD", "", "", "", "",
+        // action, quantity, type, limit, aux price
+        "BUY", "100", "LMT", "150.25", "",
+        // tif ... hidden
+        "DAY", "", "", "", "0", "", "1", "0", "0", "0", "0", "0", "0", "0",
+        // [35] shares allocation, discretionary amount, good after / till, FA, model code
+        "", "0", "", "", "", "", "", "", "",
+        // short sale, oca type, rule 80A, settling firm, all or none, min qty, percent offset
+        "0", "", "-1", "0", "", "", "0", "", "",
+        // e-trade, firm quote, NBBO cap, auction, box, stock range, override constraints
+        "0", "0", "", "0", "", "", "", "", "", "0",
+        // volatility, delta neutral order, continuous update, reference price, trail
+        "", "", "", "", "0", "", "", "",
+        // [71] scale init / subs level size, price increment, [74] table, active start / stop
+        "", "", "", "", "", "",
+        // hedge, opt out smart routing, clearing, not held, [82] delta neutral contract
+        "", "0", "", "", "0", "0",
+        // algo, algo id, what if, misc options, solicited, randomize
+        "", "", "0", "", "0", "0", "0",
+        // [90] conditions
+        "0",
+        // adjusted order, ext operator, soft dollar tier, cash qty, MiFID II
+        "", "", "", "", "", "", "0", "", "", "", "", "", "", "", "",
+        // don't use auto price for hedge, OMS container, D-peg, price management algo
+        "0", "0", "0", "",
+    ];
+
+    /// OPEN_ORDER of `lmt_order` as sent by a server without the message
+    /// version, in the order EDecoder::processOpenOrderMsg reads it
+    const OPEN_ORDER: &[&str] = &[
+        "5", "7",
+        // contract, [15] order type
+        "265598", "AAPL", "STK", "", "0", "?", "", "SMART", "USD", "AAPL", "NMS",
+        "BUY", "100", "LMT", "150.25", "1.7976931348623157E308", "DAY", "", "DU123", "", "0", "",
+        // client id, perm id, outside RTH, hidden, discretionary amount, good after, shares allocation
+        "0", "1234", "0", "0", "0", "", "",
+        // FA, model code, good till, rule 80A, percent offset, settling firm
+        "", "", "", "", "", "", "", "", "",
+        // short sale, auction, box, stock range
+        "0", "", "-1", "0", "", "", "", "", "",
+        // display size ... NBBO cap, parent id, trigger method
+        "", "0", "0", "0", "", "3", "0", "0", "", "0", "0",
+        // volatility, delta neutral order, continuous update, reference price
+        "", "0", "None", "", "0", "", "", "", "", "0", "0", "", "0", "0",
+        // trail, basis points, [78] combo legs description, legs, order legs, smart routing
+        "", "", "", "", "", "0", "0", "0",
+        // [82] scale, hedge, opt out, clearing, not held, [90] delta neutral contract, algo, solicited
+        "", "", "", "", "0", "", "", "0", "0", "", "0",
+        // what if, status, [95] margin before / change, after, commission, warning
+        "0", "PreSubmitted", "", "", "", "", "", "", "", "", "",
+        "1.7976931348623157E308", "1.7976931348623157E308", "1.7976931348623157E308", "", "",
+        // randomize, [111] conditions
+        "0", "0", "0",
+        // adjusted order, soft dollar tier, cash qty
+        "None", "", "", "", "", "", "", "0", "", "", "", "",
+        // [124] don't use auto price for hedge, OMS container, D-peg, price management algo
+        "0", "0", "0", "",
+    ];
+
+    /// Applies `edits` to `fields`, later ranges first so earlier indexes stay valid
+    fn splice(fields: &[&str], edits: &[(Range<usize>, &[&str])]) -> Vec<String> {
+        let mut fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+        for (range, replace_with) in edits.iter().rev() {
+            fields.splice(range.clone(), replace_with.iter().map(|f| f.to_string()));
+        }
+        fields
+    }
+
+    fn frame(fields: &[String]) -> Vec<u8> {
+        fields.iter().flat_map(|f| f.bytes().chain(Some(0))).collect()
+    }
+
+    fn lmt_contract() -> Contract {
+        Contract {
+            symbol: "AAPL".into(),
+            sec_type: "STK".into(),
+            exchange: "SMART".into(),
+            currency: "USD".into(),
+            ..Contract::default()
+        }
+    }
+
+    fn lmt_order() -> Order {
+        Order {
+            action: Action::BUY,
+            total_quantity: 100.0,
+            order_type: OrderType::LMT,
+            lmt_price: Some(150.25),
+            ..Order::default()
+        }
+    }
+
+    fn place_order(contract: Contract, order: Order) -> Vec<String> {
+        let request = Request::PlaceOrder { order_id: 7, contract, order };
+        protocol::frame_fields(&protocol::to_bytes(&request, SERVER_VERSION).unwrap())
+    }
+
+    fn open_order(fields: &[String], server_version: u64) -> OpenOrder {
+        match protocol::from_frame(&frame(fields), server_version).unwrap() {
+            Message::OpenOrder(open_order) => open_order,
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    fn conditions() -> Vec<OrderCondition> {
+        vec![
+            OrderCondition::Price { and_or: AndOr::And, is_more: true, price: 200.5, conid: 265598, exchange: "SMART".into(), trigger_method: TriggerMethod::Last },
+            OrderCondition::Time { and_or: AndOr::Or, is_more: false, time: "20300101 09:30:00 EST".into() },
+        ]
+    }
+
+    const CONDITIONS: &[&str] = &[
+        "2",
+        "1", "a", "1", "200.5", "265598", "SMART", "2",
+        "3", "o", "0", "20300101 09:30:00 EST",
+        // ignore RTH, cancel order
+        "1", "0",
+    ];
+
+    const SCALE: &[&str] = &[
+        "100", "50", "0.05",
+        "0.01", "60", "0.1", "1", "0", "", "0",
+    ];
+
+    fn scale_order() -> Order {
+        Order {
+            scale_init_level_size: Some(100),
+            scale_subs_level_size: Some(50),
+            scale_price_increment: Some(0.05),
+            scale_price_adjust_value: Some(0.01),
+            scale_price_adjust_interval: Some(60),
+            scale_profit_offset: Some(0.1),
+            scale_auto_reset: true,
+            scale_init_position: Some(0),
+            ..lmt_order()
+        }
+    }
+
+    fn bag_contract() -> Contract {
+        Contract {
+            sec_type: "BAG".into(),
+            combo_legs: vec![
+                ComboLeg { con_id: 111, ratio: 1, action: Action::BUY, exchange: "SMART".into(), ..ComboLeg::default() },
+                ComboLeg { con_id: 222, ratio: 1, action: Action::SELL, exchange: "SMART".into(), ..ComboLeg::default() },
+            ],
+            delta_neutral_contract: Some(DeltaNeutralContract { conid: 333, delta: 0.5, price: 101.25 }),
+            ..lmt_contract()
+        }
+    }
+
+    const BAG_LEGS: &[&str] = &[
+        "2",
+        "111", "1", "BUY", "SMART", "0", "0", "", "-1",
+        "222", "1", "SELL", "SMART", "0", "0", "", "-1",
+    ];
+
+    const DELTA_NEUTRAL_CONTRACT: &[&str] = &["1", "333", "0.5", "101.25"];
+
+    fn peg_bench_order() -> Order {
+        Order {
+            order_type: OrderType::PEG_BENCH,
+            reference_contract_id: 265598,
+            is_pegged_change_amount_decrease: true,
+            pegged_change_amount: 0.5,
+            reference_change_amount: 0.25,
+            reference_exchange_id: "NASDAQ".into(),
+            ..lmt_order()
+        }
+    }
+
+    const PEG_BENCH: &[&str] = &["265598", "1", "0.5", "0.25", "NASDAQ"];
+
+    #[test]
+    fn place_lmt_order() {
+        assert_eq!(place_order(lmt_contract(), lmt_order()), splice(PLACE_ORDER, &[]));
+    }
+
+    #[test]
+    fn place_order_version_gates() {
+        let request = Request::PlaceOrder { order_id: 7, contract: lmt_contract(), order: lmt_order() };
+
+        match protocol::to_bytes(&request, MIN_SERVER_VER_ORDER_CONTAINER - 1) {
+            Err(Error::UnsupportedVersion { min_version, .. }) => assert_eq!(min_version, MIN_SERVER_VER_ORDER_CONTAINER),
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // Without D-peg and price management algo fields
+        let fields = protocol::frame_fields(&protocol::to_bytes(&request, MIN_SERVER_VER_ORDER_CONTAINER).unwrap());
+        assert_eq!(fields, splice(PLACE_ORDER, &[(108..110, &[])]));
+    }
+
+    #[test]
+    fn place_order_with_conditions() {
+        let order = Order { conditions: conditions(), conditions_ignore_rth: true, ..lmt_order() };
+
+        assert_eq!(place_order(lmt_contract(), order), splice(PLACE_ORDER, &[(90..91, CONDITIONS)]));
+    }
+
+    #[test]
+    fn place_scale_order() {
+        assert_eq!(place_order(lmt_contract(), scale_order()), splice(PLACE_ORDER, &[(71..74, SCALE)]));
+    }
+
+    #[test]
+    fn place_bag_order() {
+        let order = Order {
+            order_combo_legs: vec![OrderComboLeg { price: Some(1.5) }, OrderComboLeg { price: None }],
+            smart_combo_routing_params: vec![TagValue { tag: "NonGuaranteed".into(), value: "1".into() }],
+            ..lmt_order()
+        };
+
+        let legs: Vec<&str> = BAG_LEGS.iter().copied()
+            .chain(["2", "1.5", ""].iter().copied())
+            .chain(["1", "NonGuaranteed", "1"].iter().copied())
+            .collect();
+
+        assert_eq!(place_order(bag_contract(), order), splice(PLACE_ORDER, &[
+            (4..5, &["BAG"]),
+            (35..35, &legs),
+            (82..83, DELTA_NEUTRAL_CONTRACT),
+        ]));
+    }
+
+    #[test]
+    fn place_peg_bench_order() {
+        assert_eq!(place_order(lmt_contract(), peg_bench_order()), splice(PLACE_ORDER, &[
+            (18..19, &["PEG BENCH"]),
+            (90..90, PEG_BENCH),
+        ]));
+    }
+
+    #[test]
+    fn open_lmt_order() {
+        let OpenOrder { contract, order, state } = open_order(&splice(OPEN_ORDER, &[]), SERVER_VERSION);
+
+        assert_eq!(contract.conid, 265598);
+        assert_eq!(contract.trading_class, "NMS");
+        assert_eq!(order.order_id, 7);
+        assert_eq!(order.perm_id, 1234);
+        assert_eq!(order.order_type, OrderType::LMT);
+        assert_eq!(order.lmt_price, Some(150.25));
+        assert_eq!(order.aux_price, None);
+        assert_eq!(order.account, "DU123");
+        assert_eq!(order.oca_type, OcaType::ReduceWithoutBlocking);
+        assert!(order.conditions.is_empty());
+        assert_eq!(state.status, "PreSubmitted");
+        assert_eq!(state.commission, None);
+    }
+
+    #[test]
+    fn open_order_before_order_container() {
+        // Message version sent, OMS container and later fields are not
+        let fields = splice(OPEN_ORDER, &[(1..1, &["34"]), (125..128, &[])]);
+        let OpenOrder { order, state, .. } = open_order(&fields, MIN_SERVER_VER_ORDER_CONTAINER - 1);
+
+        assert_eq!(order.order_id, 7);
+        assert_eq!(state.status, "PreSubmitted");
+    }
+
+    #[test]
+    fn open_order_with_conditions() {
+        let order = open_order(&splice(OPEN_ORDER, &[(111..112, CONDITIONS)]), SERVER_VERSION).order;
+
+        assert_eq!(order.conditions, conditions());
+        assert!(order.conditions_ignore_rth);
+        assert!(!order.conditions_cancel_order);
+    }
+
+    #[test]
+    fn open_scale_order() {
+        let order = open_order(&splice(OPEN_ORDER, &[(82..85, SCALE)]), SERVER_VERSION).order;
+        let expected = scale_order();
+
+        assert_eq!(order.scale_init_level_size, expected.scale_init_level_size);
+        assert_eq!(order.scale_subs_level_size, expected.scale_subs_level_size);
+        assert_eq!(order.scale_price_increment, expected.scale_price_increment);
+        assert_eq!(order.scale_price_adjust_value, expected.scale_price_adjust_value);
+        assert_eq!(order.scale_price_adjust_interval, expected.scale_price_adjust_interval);
+        assert_eq!(order.scale_profit_offset, expected.scale_profit_offset);
+        assert_eq!(order.scale_auto_reset, expected.scale_auto_reset);
+        assert_eq!(order.scale_init_position, expected.scale_init_position);
+        assert_eq!(order.scale_init_fill_qty, None);
+        assert!(!order.scale_random_percent);
+    }
+
+    #[test]
+    fn open_bag_order() {
+        let legs: Vec<&str> = ["111|1,222|-1"].iter().copied()
+            .chain(BAG_LEGS.iter().copied())
+            .chain(["2", "1.5", ""].iter().copied())
+            .collect();
+
+        let fields = splice(OPEN_ORDER, &[
+            (4..5, &["BAG"]),
+            (78..81, &legs),
+            (90..91, DELTA_NEUTRAL_CONTRACT),
+        ]);
+        let OpenOrder { contract, order, .. } = open_order(&fields, SERVER_VERSION);
+        let expected = bag_contract();
+
+        assert_eq!(contract.sec_type, "BAG");
+        assert_eq!(contract.combo_legs_descrip, "111|1,222|-1");
+        assert_eq!(contract.combo_legs, expected.combo_legs);
+        assert_eq!(contract.delta_neutral_contract, expected.delta_neutral_contract);
+        assert_eq!(order.order_combo_legs, vec![OrderComboLeg { price: Some(1.5) }, OrderComboLeg { price: None }]);
+    }
+
+    #[test]
+    fn open_peg_bench_order() {
+        let fields = splice(OPEN_ORDER, &[
+            (15..16, &["PEG BENCH"]),
+            (111..111, PEG_BENCH),
+        ]);
+        let order = open_order(&fields, SERVER_VERSION).order;
+        let expected = peg_bench_order();
+
+        assert_eq!(order.order_type, OrderType::PEG_BENCH);
+        assert_eq!(order.reference_contract_id, expected.reference_contract_id);
+        assert_eq!(order.is_pegged_change_amount_decrease, expected.is_pegged_change_amount_decrease);
+        assert_eq!(order.pegged_change_amount, expected.pegged_change_amount);
+        assert_eq!(order.reference_change_amount, expected.reference_change_amount);
+        assert_eq!(order.reference_exchange_id, expected.reference_exchange_id);
+    }
+}
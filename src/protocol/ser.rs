@@ -1,19 +1,27 @@
 use serde::{ser, Serialize};
 
 use super::Error;
+use super::version::{REQUIRES_TOKEN, SINCE_TOKEN, UNTIL_TOKEN};
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 /// We serialize to a string because the protocol expects each message to be
 /// prefixed by its byte length
+///
+/// `server_version` is the version negotiated in the handshake, used to decide
+/// whether `version::Since` fields are sent.
 #[derive(Default)]
 pub struct Serializer {
     pub output: Vec<u8>,
+    pub server_version: u64,
 }
 
 impl Serializer {
-    pub fn new() -> Serializer {
-        Default::default()
+    pub fn new(server_version: u64) -> Serializer {
+        Serializer {
+            output: Vec::new(),
+            server_version,
+        }
     }
 }
 
@@ -117,15 +125,55 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         self.serialize_str(variant)
     }
 
+    // `version::Since` sends (min_version, field). Strip the version and drop
+    // the field altogether if the server is too old.
     fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(self)
+        if name != SINCE_TOKEN && name != UNTIL_TOKEN && name != REQUIRES_TOKEN {
+            return value.serialize(self);
+        }
+
+        let start = self.output.len();
+        value.serialize(&mut *self)?;
+
+        let version_len = self.output[start..].iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| Error::Custom(format!("missing version in {}", name)))?;
+
+        let version: u64 = std::str::from_utf8(&self.output[start..start + version_len])
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| Error::Custom(format!("invalid version in {}", name)))?;
+
+        let keep = match name {
+            SINCE_TOKEN => self.server_version >= version,
+            UNTIL_TOKEN => self.server_version < version,
+            _ => {
+                self.output.truncate(start);
+                if self.server_version < version {
+                    return Err(Error::UnsupportedVersion {
+                        min_version: version,
+                        server_version: self.server_version,
+                    });
+                }
+                return Ok(());
+            },
+        };
+
+        if keep {
+            self.output.drain(start..=start + version_len);
+        }
+        else {
+            self.output.truncate(start);
+        }
+
+        Ok(())
     }
 
     // Note that newtype variant (and all of the other variant serialization
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{SeqAccess, Visitor};
+
+/// Client version range sent in the handshake by default
+pub const MIN_CLIENT_VER: u64 = 100;
+pub const MAX_CLIENT_VER: u64 = 155;
+
+// Also see EClient.h / MIN_SERVER_VER_*
+pub const MIN_SERVER_VER_PEGGED_TO_BENCHMARK: u64 = 102;
+pub const MIN_SERVER_VER_MODELS_SUPPORT: u64 = 103;
+pub const MIN_SERVER_VER_SOFT_DOLLAR_TIER: u64 = 106;
+pub const MIN_SERVER_VER_MD_SIZE_MULTIPLIER: u64 = 110;
+pub const MIN_SERVER_VER_CASH_QTY: u64 = 111;
+pub const MIN_SERVER_VER_REQ_SMART_COMPONENTS: u64 = 114;
+pub const MIN_SERVER_VER_AGG_GROUP: u64 = 121;
+pub const MIN_SERVER_VER_UNDERLYING_INFO: u64 = 122;
+pub const MIN_SERVER_VER_SYNT_REALTIME_BARS: u64 = 124;
+pub const MIN_SERVER_VER_MARKET_RULES: u64 = 126;
+pub const MIN_SERVER_VER_MARKET_CAP_PRICE: u64 = 131;
+pub const MIN_SERVER_VER_REAL_EXPIRATION_DATE: u64 = 134;
+pub const MIN_SERVER_VER_TICK_BY_TICK: u64 = 137;
+pub const MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE: u64 = 140;
+pub const MIN_SERVER_VER_AUTO_PRICE_FOR_HEDGE: u64 = 141;
+pub const MIN_SERVER_VER_WHAT_IF_EXT_FIELDS: u64 = 142;
+pub const MIN_SERVER_VER_SCANNER_GENERIC_OPTS: u64 = 143;
+pub const MIN_SERVER_VER_ORDER_CONTAINER: u64 = 145;
+pub const MIN_SERVER_VER_SMART_DEPTH: u64 = 146;
+pub const MIN_SERVER_VER_D_PEG_ORDERS: u64 = 148;
+pub const MIN_SERVER_VER_MKT_DEPTH_PRIM_EXCHANGE: u64 = 149;
+pub const MIN_SERVER_VER_PRICE_MGMT_ALGO: u64 = 151;
+pub const MIN_SERVER_VER_STOCK_TYPE: u64 = 152;
+
+/// Name used by `Since` to signal the `Serializer` / `Deserializer` that the
+/// field is gated by server version.
+pub(crate) const SINCE_TOKEN: &str = "$ibapi::Since";
+/// Same as `SINCE_TOKEN`, for `Until`
+pub(crate) const UNTIL_TOKEN: &str = "$ibapi::Until";
+/// Same as `SINCE_TOKEN`, for `Requires`
+pub(crate) const REQUIRES_TOKEN: &str = "$ibapi::Requires";
+
+/// A field that is only sent when the negotiated server version is at least `V`.
+///
+/// Deserializes to `None` without consuming input when the server is older, and
+/// is omitted entirely from the output when serializing for an older server.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Since<T, const V: u64>(pub Option<T>);
+
+impl<T, const V: u64> Since<T, V> {
+    pub fn into_inner(self) -> Option<T> {
+        self.0
+    }
+}
+
+impl<T, const V: u64> From<T> for Since<T, V> {
+    fn from(t: T) -> Self {
+        Since(Some(t))
+    }
+}
+
+/// `Serializer` receives the minimum version as the first field and strips it
+/// from the output, see `ser::Serializer::serialize_newtype_struct`.
+impl<T: Serialize, const V: u64> Serialize for Since<T, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(SINCE_TOKEN, &(V, &self.0))
+    }
+}
+
+/// `Deserializer` yields the server version as the first element of a
+/// sequence, followed by the field itself, see
+/// `de::Deserializer::deserialize_newtype_struct`.
+impl<'de, T: Deserialize<'de>, const V: u64> Deserialize<'de> for Since<T, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SinceVisitor<T, const V: u64>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>, const V: u64> Visitor<'de> for SinceVisitor<T, V> {
+            type Value = Since<T, V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "field since server version {}", V)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let server_version: u64 = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+
+                if server_version < V {
+                    return Ok(Since(None));
+                }
+
+                let t: T = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+
+                Ok(Since(Some(t)))
+            }
+
+            // Other formats just see the field
+            fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+                Option::deserialize(deserializer).map(Since)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(SINCE_TOKEN, SinceVisitor(PhantomData))
+    }
+}
+
+/// A field that is only sent while the negotiated server version is below `V`,
+/// typically a message version that newer servers dropped.
+///
+/// The counterpart of `Since`: deserializes to `None` without consuming input
+/// when the server is at least `V`, and is omitted when serializing for it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Until<T, const V: u64>(pub Option<T>);
+
+impl<T, const V: u64> Until<T, V> {
+    pub fn into_inner(self) -> Option<T> {
+        self.0
+    }
+}
+
+impl<T, const V: u64> From<T> for Until<T, V> {
+    fn from(t: T) -> Self {
+        Until(Some(t))
+    }
+}
+
+/// Same encoding as `Since`, the `Serializer` applies the opposite check.
+impl<T: Serialize, const V: u64> Serialize for Until<T, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(UNTIL_TOKEN, &(V, &self.0))
+    }
+}
+
+impl<'de, T: Deserialize<'de>, const V: u64> Deserialize<'de> for Until<T, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct UntilVisitor<T, const V: u64>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>, const V: u64> Visitor<'de> for UntilVisitor<T, V> {
+            type Value = Until<T, V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "field until server version {}", V)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let server_version: u64 = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+
+                if server_version >= V {
+                    return Ok(Until(None));
+                }
+
+                let t: T = seq.next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+
+                Ok(Until(Some(t)))
+            }
+
+            // Other formats just see the field
+            fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+                Option::deserialize(deserializer).map(Until)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(UNTIL_TOKEN, UntilVisitor(PhantomData))
+    }
+}
+
+/// Marks a request that only exists since server version `V`. Writes nothing,
+/// but makes the `Serializer` fail with `Error::UnsupportedVersion` for older
+/// servers instead of sending a request they would reject.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Requires<const V: u64>;
+
+impl<const V: u64> Serialize for Requires<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(REQUIRES_TOKEN, &V)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ib::{Contract, Message, Request, TickByTickType};
+    use crate::protocol::{self, Error};
+    use super::*;
+
+    fn frame(fields: &[&str]) -> Vec<u8> {
+        fields.iter().flat_map(|f| f.bytes().chain(Some(0))).collect()
+    }
+
+    fn order_status(server_version: u64, fields: &[&str]) -> (i32, f64) {
+        match protocol::from_frame(&frame(fields), server_version).unwrap() {
+            Message::OrderStatus { order_id, mkt_cap_price, .. } => (order_id, mkt_cap_price),
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    #[test]
+    fn order_status_version_gates() {
+        let old = order_status(MIN_SERVER_VER_MARKET_CAP_PRICE - 1,
+            &["3", "6", "7", "Submitted", "0", "100", "0", "123", "0", "0", "1", ""]);
+        assert_eq!(old, (7, 0.0));
+
+        let new = order_status(MIN_SERVER_VER_MARKET_CAP_PRICE,
+            &["3", "7", "Submitted", "0", "100", "0", "123", "0", "0", "1", "", "1.5"]);
+        assert_eq!(new, (7, 1.5));
+    }
+
+    #[test]
+    fn historical_data_until_synt_realtime_bars() {
+        let old = frame(&["17", "3", "5", "a", "b", "1", "t", "1", "2", "0.5", "1.5", "10", "1.2", "false", "4"]);
+        let new = frame(&["17", "5", "a", "b", "1", "t", "1", "2", "0.5", "1.5", "10", "1.2", "4"]);
+
+        for (server_version, frame) in &[(MIN_SERVER_VER_SYNT_REALTIME_BARS - 1, old), (MIN_SERVER_VER_SYNT_REALTIME_BARS, new)] {
+            match protocol::from_frame(frame, *server_version).unwrap() {
+                Message::HistoricalData { req_id, bars, .. } => {
+                    assert_eq!(req_id, 5);
+                    assert_eq!(bars.len(), 1);
+                    assert_eq!(bars[0].count, 4);
+                },
+                m => panic!("unexpected {:?}", m),
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_mkt_depth_since_smart_depth() {
+        let request = Request::CancelMktDepth { ticker_id: 3, is_smart_depth: true };
+
+        assert_eq!(protocol::to_bytes(&request, MIN_SERVER_VER_SMART_DEPTH - 1).unwrap(), frame(&["11", "1", "3"]));
+        assert_eq!(protocol::to_bytes(&request, MIN_SERVER_VER_SMART_DEPTH).unwrap(), frame(&["11", "1", "3", "1"]));
+    }
+
+    #[test]
+    fn tick_by_tick_requires_server_version() {
+        let request = Request::ReqTickByTickData {
+            req_id: 1,
+            contract: Contract::default(),
+            tick_type: TickByTickType::Last,
+            number_of_ticks: 0,
+            ignore_size: false,
+        };
+
+        match protocol::to_bytes(&request, MIN_SERVER_VER_TICK_BY_TICK - 1) {
+            Err(Error::UnsupportedVersion { min_version, .. }) => assert_eq!(min_version, MIN_SERVER_VER_TICK_BY_TICK),
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let old = protocol::to_bytes(&request, MIN_SERVER_VER_TICK_BY_TICK).unwrap();
+        let new = protocol::to_bytes(&request, MIN_SERVER_VER_TICK_BY_TICK_IGNORE_SIZE).unwrap();
+        assert_eq!(new.len(), old.len() + "0\0".len() * 2);
+        assert!(old.starts_with(b"97\x001\x00"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use crossbeam_channel::{SendError, Sender};
+
+use crate::ib::Message;
+
+/// A message as received by a `Socket`, with when and in which order it
+/// arrived
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    /// Counts the messages of a `Socket` from 0, across reconnections and
+    /// including the ones generated locally, e.g. `Disconnected`
+    pub seq: u64,
+    /// Local time the frame was read, for measuring intervals
+    pub received: Instant,
+    /// Local wall-clock time the frame was read
+    pub received_at: SystemTime,
+    /// Length of the frame without its length prefix, 0 for messages
+    /// generated locally
+    pub frame_len: usize,
+    pub message: Message,
+}
+
+impl Envelope {
+    pub fn into_message(self) -> Message {
+        self.message
+    }
+}
+
+impl Deref for Envelope {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        &self.message
+    }
+}
+
+/// `Socket::probe_clock` calls waiting for `CurrentTime`, oldest first
+pub(crate) type ClockProbes = Arc<Mutex<VecDeque<Sender<Envelope>>>>;
+
+/// Stamps and sends envelopes. Cloned for each reader thread of a supervised
+/// `Socket`, sequence numbers follow the order in which they are sent.
+#[derive(Clone, Debug)]
+pub(crate) struct EnvelopeSender {
+    tx: Sender<Envelope>,
+    next_seq: Arc<Mutex<u64>>,
+    clock_probes: ClockProbes,
+}
+
+impl EnvelopeSender {
+    pub(crate) fn new(tx: Sender<Envelope>) -> EnvelopeSender {
+        EnvelopeSender { tx, next_seq: Default::default(), clock_probes: Default::default() }
+    }
+
+    pub(crate) fn clock_probes(&self) -> ClockProbes {
+        self.clock_probes.clone()
+    }
+
+    /// Fails if the `Socket` was dropped. `CurrentTime` goes to the oldest
+    /// waiting clock probe instead, if any, but still takes up a `seq`.
+    pub(crate) fn send(&self, message: Message, frame_len: usize, received: Instant, received_at: SystemTime) -> Result<(), SendError<()>> {
+        let mut next_seq = self.next_seq.lock().unwrap();
+        let envelope = Envelope { seq: *next_seq, received, received_at, frame_len, message };
+
+        let envelope = match envelope.message {
+            Message::CurrentTime { .. } => self.to_clock_probe(envelope),
+            _ => Some(envelope),
+        };
+        if let Some(envelope) = envelope {
+            self.tx.send(envelope).map_err(|_| SendError(()))?;
+        }
+        *next_seq += 1;
+        Ok(())
+    }
+
+    /// Returns the envelope if no probe is waiting for it any more
+    fn to_clock_probe(&self, envelope: Envelope) -> Option<Envelope> {
+        let probe = match self.clock_probes.lock().unwrap().pop_front() {
+            Some(probe) => probe,
+            None => return Some(envelope),
+        };
+        probe.send(envelope).err().map(|err| err.0)
+    }
+
+    /// Sends a message generated locally, received now
+    pub(crate) fn send_local(&self, message: Message) -> Result<(), SendError<()>> {
+        self.send(message, 0, Instant::now(), SystemTime::now())
+    }
+}
@@ -0,0 +1,763 @@
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, error, info, warn};
+use crossbeam_channel::{bounded, select, Receiver, SendError, Sender, unbounded};
+use smart_default::SmartDefault;
+
+use crate::protocol;
+use crate::protocol::DecodeError;
+use crate::protocol::version::{MAX_CLIENT_VER, MIN_CLIENT_VER};
+use crate::ib::{ErrorCode, Hello, Message, Request};
+
+use self::envelope::{ClockProbes, EnvelopeSender};
+pub(crate) use self::pacer::{Pacer, WriterMetrics};
+use self::supervisor::Connection;
+
+pub use self::envelope::Envelope;
+pub use self::pacer::{RateLimit, WriterStats};
+pub use self::transport::Transport;
+
+mod envelope;
+mod pacer;
+#[cfg(test)]
+pub(crate) mod pipe;
+mod subscriptions;
+mod supervisor;
+mod transport;
+
+pub struct Socket {
+    /// Received messages with their metadata, `Envelope` derefs to `Message`
+    pub rx: Receiver<Envelope>,
+    pub tx: Sender<Request>,
+    /// Negotiated in the handshake, within the requested client version range
+    pub server_version: u64,
+    connected: Arc<AtomicBool>,
+    writer_metrics: Arc<WriterMetrics>,
+    clock_probes: ClockProbes,
+    /// Never sent on, the supervisor sees it disconnect when the `Socket` is dropped
+    _dropped: Sender<()>,
+}
+
+/// Connection settings, built with chained setters:
+///
+/// ```no_run
+/// # use ibapi::socket::ConnectOptions;
+/// let ib = ConnectOptions::new().client_id(7).connect("127.0.0.1:7496");
+/// ```
+#[derive(Clone, Debug, SmartDefault)]
+pub struct ConnectOptions {
+    client_id: i32,
+    optional_capabilities: String,
+    #[default(MIN_CLIENT_VER)]
+    min_version: u64,
+    #[default(MAX_CLIENT_VER)]
+    max_version: u64,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    #[default(true)]
+    pub(crate) nodelay: bool,
+    pub(crate) keepalive: bool,
+    #[default("IB Socket Reader".into())]
+    reader_thread_name: String,
+    #[default("IB Socket Writer".into())]
+    writer_thread_name: String,
+    #[default(Duration::from_secs(1))]
+    reconnect_min_delay: Duration,
+    #[default(Duration::from_secs(60))]
+    reconnect_max_delay: Duration,
+    #[default(Some(RateLimit::default()))]
+    rate_limit: Option<RateLimit>,
+    #[default(true)]
+    prioritize_cancels: bool,
+}
+
+impl ConnectOptions {
+    pub fn new() -> ConnectOptions {
+        Default::default()
+    }
+
+    /// Each connection to the same TWS / gateway needs a unique client id.
+    /// Client id 0 can also see orders entered in TWS.
+    pub fn client_id(mut self, client_id: i32) -> Self {
+        self.client_id = client_id;
+        self
+    }
+
+    pub fn optional_capabilities<S: Into<String>>(mut self, optional_capabilities: S) -> Self {
+        self.optional_capabilities = optional_capabilities.into();
+        self
+    }
+
+    /// Client versions advertised in the handshake. The server picks the
+    /// highest version it supports in that range.
+    pub fn versions(mut self, min_version: u64, max_version: u64) -> Self {
+        self.min_version = min_version;
+        self.max_version = max_version;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum time to wait for the server version after connecting
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: bool) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    pub fn thread_names<S: Into<String>>(mut self, reader: S, writer: S) -> Self {
+        self.reader_thread_name = reader.into();
+        self.writer_thread_name = writer.into();
+        self
+    }
+
+    /// Delay before the first reconnection attempt of a supervised `Socket`,
+    /// doubling after each failure up to `max`.
+    pub fn reconnect_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.reconnect_min_delay = min;
+        self.reconnect_max_delay = max;
+        self
+    }
+
+    /// Limits outgoing messages to `rate` per second, allowing bursts of
+    /// `burst` messages. Defaults to 40 per second with bursts of 10.
+    ///
+    /// Panics unless `rate` is positive.
+    pub fn rate_limit(mut self, rate: f64, burst: u32) -> Self {
+        assert!(rate > 0.0, "rate limit must be positive, got {}", rate);
+        self.rate_limit = Some(RateLimit { rate, burst });
+        self
+    }
+
+    pub fn no_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+
+    /// Send cancels (e.g. `CancelMktData`) ahead of other requests waiting
+    /// for the rate limit. On by default.
+    pub fn prioritize_cancels(mut self, prioritize_cancels: bool) -> Self {
+        self.prioritize_cancels = prioritize_cancels;
+        self
+    }
+
+    pub(crate) fn pacer(&self) -> (Pacer, Arc<WriterMetrics>) {
+        let metrics = Arc::new(WriterMetrics::default());
+        (Pacer::new(self.rate_limit, self.prioritize_cancels, metrics.clone()), metrics)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<Socket, ConnectError> {
+        let stream = self.open_stream(addr)?;
+
+        info!("Connected");
+
+        self.connect_transport(stream)
+    }
+
+    /// Performs the handshake on an already connected `transport` and starts
+    /// the reader and writer threads. TCP settings such as `nodelay` and
+    /// timeouts other than `handshake_timeout` don't apply.
+    ///
+    /// `Disconnected` is sent on `rx` when the connection drops.
+    pub fn connect_transport<T: Transport>(&self, mut transport: T) -> Result<Socket, ConnectError> {
+        let server_version = self.handshake(&mut transport)?;
+
+        let reader = BufReader::new(transport.reader()?);
+
+        let (reader_tx, reader_rx) = unbounded();
+        let reader_tx = EnvelopeSender::new(reader_tx);
+        let clock_probes = reader_tx.clock_probes();
+        let messages = reader_tx.clone();
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let reader_connected = connected.clone();
+
+        thread::Builder::new()
+            .name(self.reader_thread_name.clone())
+            .spawn(move|| {
+                read_loop(reader, reader_tx.clone(), server_version);
+                reader_connected.store(false, Ordering::Relaxed);
+                let _ = reader_tx.send_local(Message::Disconnected);
+            })?;
+
+        let (writer_tx, writer_rx) = unbounded();
+        let (pacer, writer_metrics) = self.pacer();
+        let writer_connected = connected.clone();
+
+        thread::Builder::new()
+            .name(self.writer_thread_name.clone())
+            .spawn(move|| {
+                write_loop(&mut transport, writer_rx, messages, server_version, pacer);
+                writer_connected.store(false, Ordering::Relaxed);
+                // Unblocks the reader thread
+                let _ = transport.shutdown();
+            })?;
+
+        let (dropped, _) = bounded(0);
+
+        Ok(Socket {
+            rx: reader_rx,
+            tx: writer_tx,
+            server_version,
+            connected,
+            writer_metrics,
+            clock_probes,
+            _dropped: dropped,
+        })
+    }
+
+    /// Connects like `connect`, but when the connection drops it reconnects
+    /// over TCP with exponential backoff, restarts the API and sends active
+    /// subscriptions (market data, account updates, positions, open orders)
+    /// again.
+    ///
+    /// `Connected`, `Disconnected` and `Reconnected` are sent on `rx`. Requests
+    /// sent while disconnected are queued. `Socket::server_version` is the
+    /// version of the first connection.
+    ///
+    /// Requests that fail to write when the connection drops are sent again
+    /// after reconnecting, except `PlaceOrder`, which is answered with a local
+    /// `FailSendOrder` error for its order id rather than risk placing it
+    /// twice. Requests written just before the connection dropped may still
+    /// be lost.
+    pub fn connect_supervised<A: ToSocketAddrs>(&self, addr: A) -> Result<Socket, ConnectError> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        let options = self.clone();
+
+        self.connect_supervised_with(move|| options.open_stream(&addrs[..]))
+    }
+
+    /// Like `connect_supervised`, over transports opened by `open`, e.g. Unix
+    /// domain sockets. `open` is called for the first connection and for
+    /// each reconnection attempt.
+    pub fn connect_supervised_with<T, F>(&self, mut open: F) -> Result<Socket, ConnectError>
+        where T: Transport, F: FnMut() -> Result<T, ConnectError> + Send + 'static
+    {
+        let (reader_tx, reader_rx) = unbounded();
+        let reader_tx = EnvelopeSender::new(reader_tx);
+        let clock_probes = reader_tx.clock_probes();
+
+        let conn = Connection::open(self, &mut open, &reader_tx)?;
+        let server_version = conn.server_version;
+
+        info!("Connected");
+        reader_tx.send_local(Message::Connected { server_version }).unwrap();
+
+        let (writer_tx, writer_rx) = unbounded();
+        let (pacer, writer_metrics) = self.pacer();
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let (dropped, dropped_rx) = bounded(0);
+
+        let options = self.clone();
+        let supervisor_connected = connected.clone();
+        thread::Builder::new()
+            .name(self.writer_thread_name.clone())
+            .spawn(move|| supervisor::supervise(options, open, conn, writer_rx, dropped_rx, reader_tx, supervisor_connected, pacer))?;
+
+        Ok(Socket {
+            rx: reader_rx,
+            tx: writer_tx,
+            server_version,
+            connected,
+            writer_metrics,
+            clock_probes,
+            _dropped: dropped,
+        })
+    }
+
+    /// Tries each resolved address in turn, like `TcpStream::connect`
+    fn open_stream<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpStream, ConnectError> {
+        let mut last_err = None;
+
+        for addr in addr.to_socket_addrs()? {
+            let stream = match self.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+
+            match stream {
+                Ok(stream) => {
+                    stream.set_nodelay(self.nodelay)?;
+                    socket2::SockRef::from(&stream).set_keepalive(self.keepalive)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to"))
+            .into())
+    }
+
+    /// Sends the version range, reads the server version and starts the API.
+    fn handshake<T: Transport>(&self, stream: &mut T) -> Result<u64, ConnectError> {
+        stream.write_all(&self.handshake_prefix())?;
+
+        stream.set_read_timeout(self.handshake_timeout)?;
+
+        // Not buffered, so nothing past the frame is consumed
+        let frame = match protocol::read_frame(stream) {
+            Ok(frame) => frame,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+                return Err(ConnectError::HandshakeTimeout),
+            Err(err) => return Err(err.into()),
+        };
+
+        let hello: Hello = protocol::from_frame(&frame, 0)
+            .map_err(ConnectError::Handshake)?;
+        info!("{:?}", hello);
+
+        stream.set_read_timeout(None)?;
+
+        let server_version = hello.server_version;
+
+        protocol::to_writer(stream, &self.start_api(), server_version)?;
+        info!("Sent START_API");
+
+        Ok(server_version)
+    }
+
+    /// "API" followed by the supported client version range
+    pub(crate) fn handshake_prefix(&self) -> Vec<u8> {
+        let version = format!("v{}..{}", self.min_version, self.max_version);
+
+        let mut prefix = b"API\0".to_vec();
+        prefix.extend_from_slice(&(version.len() as u32).to_be_bytes());
+        prefix.extend_from_slice(version.as_bytes());
+        prefix
+    }
+
+    pub(crate) fn start_api(&self) -> Request {
+        Request::StartApi {
+            client_id: self.client_id,
+            optional_capabilities: self.optional_capabilities.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    Io(io::Error),
+    /// No server version received within `handshake_timeout`
+    HandshakeTimeout,
+    /// Server version could not be decoded
+    Handshake(DecodeError),
+    Encode(protocol::Error),
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectError::Io(err) => Some(err),
+            ConnectError::HandshakeTimeout => None,
+            ConnectError::Handshake(err) => Some(err),
+            ConnectError::Encode(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectError::Io(err) => write!(fmt, "IO error: {}", err),
+            ConnectError::HandshakeTimeout => write!(fmt, "Handshake timed out"),
+            ConnectError::Handshake(err) => write!(fmt, "Handshake failed: {}", err),
+            ConnectError::Encode(err) => write!(fmt, "Cannot encode START_API: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(err: io::Error) -> ConnectError {
+        ConnectError::Io(err)
+    }
+}
+
+impl From<protocol::Error> for ConnectError {
+    fn from(err: protocol::Error) -> ConnectError {
+        match err {
+            protocol::Error::Io(err) => ConnectError::Io(err),
+            err => ConnectError::Encode(err),
+        }
+    }
+}
+
+impl Socket {
+    /// Connects with default `ConnectOptions`, i.e. client id 0
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Socket, ConnectError> {
+        ConnectOptions::new().connect(addr)
+    }
+}
+
+impl Socket {
+    /// Queues `request` for the writer thread. Fails if the writer thread has
+    /// stopped, i.e. the connection is gone.
+    #[allow(clippy::result_large_err)]
+    pub fn request(&self, request: Request) -> Result<(), SendError<Request>> {
+        self.tx.send(request)
+    }
+
+    /// False once the connection is lost. A supervised `Socket` is connected
+    /// again after reconnecting.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Requests waiting for the rate limit and totals since connecting
+    pub fn writer_stats(&self) -> WriterStats {
+        let mut stats = self.writer_metrics.stats();
+        stats.queue_depth += self.tx.len();
+        stats
+    }
+
+    /// Sends `ReqCurrentTime` and blocks until `CurrentTime` arrives or
+    /// `timeout` elapses.
+    ///
+    /// The reply is taken out of the messages before they reach `rx`, all
+    /// other messages are delivered as usual. Replies are handed to probes in
+    /// the order the probes were started, so `ReqCurrentTime` should not be
+    /// sent by other means while probing.
+    pub fn probe_clock(&self, timeout: Duration) -> Option<ClockProbe> {
+        let (tx, rx) = bounded(1);
+        self.clock_probes.lock().unwrap().push_back(tx.clone());
+
+        let probe = ClockProbe::start();
+        let reply = match self.request(Request::ReqCurrentTime) {
+            Ok(()) => rx.recv_timeout(timeout).ok().map(Envelope::into_message),
+            Err(_) => None,
+        };
+
+        if let Some(Message::CurrentTime { time, .. }) = reply {
+            return Some(probe.finish(time));
+        }
+
+        // Not sent or timed out, a late reply goes to the next probe or to `rx`
+        self.clock_probes.lock().unwrap().retain(|probe| !probe.same_channel(&tx));
+        None
+    }
+}
+
+/// Round-trip latency and clock skew between the local host and TWS, measured
+/// with `ReqCurrentTime`.
+///
+/// TWS only reports whole seconds, so `skew` is accurate to about +/- 1s.
+#[derive(Clone, Debug)]
+pub struct ClockProbe {
+    sent_instant: Instant,
+    sent_time: SystemTime,
+    /// Time between sending `ReqCurrentTime` and receiving `CurrentTime`
+    pub round_trip: Duration,
+    /// Server time as reported in `CurrentTime`
+    pub server_time: SystemTime,
+    /// Seconds the server clock is ahead of the local clock (negative if behind),
+    /// assuming the reply was stamped halfway through the round trip.
+    pub skew: f64,
+}
+
+impl ClockProbe {
+    /// Call immediately before sending `ReqCurrentTime` when driving the
+    /// request from your own message loop.
+    pub fn start() -> ClockProbe {
+        ClockProbe {
+            sent_instant: Instant::now(),
+            sent_time: SystemTime::now(),
+            round_trip: Duration::default(),
+            server_time: UNIX_EPOCH,
+            skew: 0.0,
+        }
+    }
+
+    /// Call with `time` from the `CurrentTime` message.
+    pub fn finish(mut self, time: i64) -> ClockProbe {
+        self.round_trip = self.sent_instant.elapsed();
+        self.server_time = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+
+        let local_time = self.sent_time + self.round_trip / 2;
+        self.skew = match self.server_time.duration_since(local_time) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        };
+
+        self
+    }
+}
+
+/// Frames are length-prefixed, so reading continues with the next frame after
+/// a message fails to decode. Only I/O errors stop the loop.
+///
+/// Messages are stamped with the time the frame was read, before decoding.
+fn read_loop<R: Read>(mut reader: BufReader<R>, tx: EnvelopeSender, server_version: u64) {
+    loop {
+        let frame = match protocol::read_frame(&mut reader) {
+            Ok(frame) => frame,
+            Err(err) => {
+                error!("Read error: {}", err);
+                break; // drop channel
+            }
+        };
+        let (received, received_at) = (Instant::now(), SystemTime::now());
+
+        let msg = decode_frame(&frame, server_version);
+
+        if tx.send(msg, frame.len(), received, received_at).is_err() {
+            break; // Socket dropped
+        }
+    }
+}
+
+
+/// A message that fails to decode is passed on as raw fields
+pub(crate) fn decode_frame(frame: &[u8], server_version: u64) -> Message {
+    match protocol::from_frame(frame, server_version) {
+        Err(DecodeError::UnknownMessageId(id)) => {
+            warn!("Unimplemented message ID: {}", id);
+            Message::UnknownMessage(protocol::frame_fields(frame))
+        }
+        Err(err) => {
+            error!("Decode error: {}", err);
+            let error = err.to_string();
+            Message::DecodeFailed { error, fields: protocol::frame_fields(frame) }
+        }
+        Ok(data) => {
+            debug!("data: {:?}", data);
+            data
+        }
+    }
+}
+
+/// Requests still queued when the `Socket` is dropped are written before exiting.
+/// Exits on the first I/O error, so `Socket::request` fails afterwards.
+fn write_loop<W: Write>(mut stream: W, rx: Receiver<Request>, messages: EnvelopeSender, server_version: u64, mut pacer: Pacer) {
+    loop {
+        select! {
+            recv(rx) -> request => match request {
+                Ok(request) => pacer.push(request),
+                Err(_) => break,
+            },
+            recv(pacer.ready()) -> _ => if let Some(request) = pacer.pop() {
+                if write(&mut stream, &request, &messages, server_version).is_err() {
+                    return;
+                }
+            },
+        }
+    }
+
+    while let Some(wait) = pacer.wait_time() {
+        thread::sleep(wait);
+        if let Some(request) = pacer.pop() {
+            if write(&mut stream, &request, &messages, server_version).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Requests that cannot be encoded are logged and skipped, see
+/// `unsupported_request`. Fails on I/O errors.
+fn write<W: Write>(stream: &mut W, request: &Request, messages: &EnvelopeSender, server_version: u64) -> io::Result<()> {
+    match protocol::to_writer(stream, request, server_version) {
+        Ok(()) => Ok(()),
+        Err(protocol::Error::Io(err)) => {
+            error!("Write error: {}", err);
+            warn!("Request not sent: {:?}", request);
+            Err(err)
+        },
+        Err(err) => {
+            error!("Cannot encode {:?}: {}", request, err);
+            if let Some(msg) = unsupported_request(request, &err) {
+                let _ = messages.send_local(msg);
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Like EClient, answers a request the server version is too old for with a
+/// local `UpdateTws` error for its id, so callers waiting on it are not left
+/// hanging.
+pub(crate) fn unsupported_request(request: &Request, err: &protocol::Error) -> Option<Message> {
+    match err {
+        protocol::Error::UnsupportedVersion { .. } => Some(Message::ErrMsg {
+            version: 2,
+            id: request.id().unwrap_or(-1),
+            error_code: ErrorCode::UpdateTws,
+            error_msg: err.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+impl <'a>Iterator for &'a Socket {
+    type Item = Envelope;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use crate::ib::{ErrorCode, Message, Request};
+    use super::{pipe, ConnectOptions, Socket};
+    use super::pipe::FakeGateway;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn recv(socket: &Socket) -> Message {
+        socket.rx.recv_timeout(TIMEOUT).unwrap().into_message()
+    }
+
+    #[test]
+    fn handshake_and_round_trip() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new().client_id(7), 151);
+
+        assert_eq!(socket.server_version, 151);
+        assert_eq!(gateway.start_api, ["71", "2", "7", ""]);
+        assert!(socket.is_connected());
+
+        socket.request(Request::ReqCurrentTime).unwrap();
+        assert_eq!(gateway.recv(), ["49", "1"]);
+
+        gateway.send(&["49", "1", "1704205800"]);
+        assert!(matches!(recv(&socket), Message::CurrentTime { time: 1704205800, .. }));
+    }
+
+    #[test]
+    fn closed_connection_disconnects() {
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        drop(gateway);
+
+        assert!(matches!(recv(&socket), Message::Disconnected));
+        assert!(!socket.is_connected());
+
+        // The writer only notices on its next write
+        let _ = socket.request(Request::ReqCurrentTime);
+
+        assert!(socket.rx.recv_timeout(TIMEOUT).is_err());
+        assert!(socket.request(Request::ReqCurrentTime).is_err());
+    }
+
+    #[test]
+    fn dropping_the_socket_closes_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = thread::spawn(move|| {
+            let stream = listener.accept().unwrap().0;
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            FakeGateway::accept(stream, 151)
+        });
+        let socket = ConnectOptions::new().connect(addr).unwrap();
+        let mut gateway = gateway.join().unwrap();
+
+        let started = Instant::now();
+        drop(socket);
+        assert!(gateway.try_recv().is_err());
+        assert!(started.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn envelopes_are_numbered_and_stamped() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        let started = (Instant::now(), SystemTime::now());
+
+        gateway.send(&["49", "1", "1704205800"]);
+        gateway.send(&["4", "2", "-1", "2104", "Market data farm connection is OK:usfarm"]);
+        drop(gateway);
+
+        let envelopes: Vec<_> = (0..3).map(|_| socket.rx.recv_timeout(TIMEOUT).unwrap()).collect();
+
+        assert!(matches!(envelopes[2].message, Message::Disconnected));
+        assert_eq!(envelopes.iter().map(|e| e.seq).collect::<Vec<_>>(), [0, 1, 2]);
+        // Fields with their terminating 0 bytes
+        assert_eq!(envelopes.iter().map(|e| e.frame_len).collect::<Vec<_>>(), [16, 53, 0]);
+        for pair in envelopes.windows(2) {
+            assert!(pair[0].received <= pair[1].received);
+        }
+        assert!(envelopes[0].received >= started.0 && envelopes[0].received_at >= started.1);
+    }
+
+    #[test]
+    fn probe_clock_takes_the_reply() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 151);
+
+        let gateway = thread::spawn(move|| {
+            assert_eq!(gateway.recv(), ["49", "1"]);
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            gateway.send(&["49", "1", &now.to_string()]);
+            gateway.send(&["49", "1", &now.to_string()]);
+            gateway
+        });
+
+        let probe = socket.probe_clock(TIMEOUT).unwrap();
+        assert!(probe.skew.abs() < 2.0, "{:?}", probe);
+        assert!(probe.round_trip < TIMEOUT);
+
+        // Replies without a probe waiting go to `rx`
+        assert!(matches!(recv(&socket), Message::CurrentTime { .. }));
+        drop(gateway.join().unwrap());
+    }
+
+    #[test]
+    fn probe_clock_unregisters_when_not_sent() {
+        let (socket, gateway) = pipe::connect(&ConnectOptions::new(), 151);
+        drop(gateway);
+
+        let deadline = Instant::now() + TIMEOUT;
+        while socket.request(Request::ReqCurrentTime).is_ok() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(socket.probe_clock(TIMEOUT).is_none());
+        assert!(socket.clock_probes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "rate limit must be positive")]
+    fn rate_limit_must_be_positive() {
+        let _ = ConnectOptions::new().rate_limit(f64::NAN, 10);
+    }
+
+    #[test]
+    fn request_too_new_for_server_fails_locally() {
+        let (socket, mut gateway) = pipe::connect(&ConnectOptions::new(), 136);
+
+        socket.request(Request::ReqTickByTickData {
+            req_id: 3,
+            contract: Default::default(),
+            tick_type: Default::default(),
+            number_of_ticks: 0,
+            ignore_size: false,
+        }).unwrap();
+        socket.request(Request::ReqCurrentTime).unwrap();
+
+        assert!(matches!(recv(&socket), Message::ErrMsg { id: 3, error_code: ErrorCode::UpdateTws, .. }));
+        assert_eq!(gateway.recv(), ["49", "1"]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{after, never, Receiver};
+
+use crate::ib::Request;
+
+/// Token bucket limiting outgoing messages. TWS disconnects clients sending
+/// more than 50 messages per second, and a full bucket can be sent at once on
+/// top of `rate`, so keep `rate + burst` below that.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Messages per second
+    pub rate: f64,
+    /// Messages that can be sent at once after being idle
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit { rate: 40.0, burst: 10 }
+    }
+}
+
+/// Writer queue statistics, see `Socket::writer_stats`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriterStats {
+    /// Requests waiting to be written
+    pub queue_depth: usize,
+    /// Highest `queue_depth` since connecting
+    pub max_queue_depth: usize,
+    /// Requests written
+    pub sent: u64,
+    /// Requests that had to wait for the rate limit
+    pub throttled: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct WriterMetrics {
+    queue_depth: AtomicUsize,
+    max_queue_depth: AtomicUsize,
+    sent: AtomicU64,
+    throttled: AtomicU64,
+}
+
+impl WriterMetrics {
+    pub(crate) fn stats(&self) -> WriterStats {
+        WriterStats {
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            max_queue_depth: self.max_queue_depth.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Requests queued by the writer thread, released according to the rate limit.
+///
+/// Cancels are sent before other queued requests if `prioritize_cancels` is
+/// set, unless the request they cancel is still queued.
+/// Subscriptions replayed after reconnecting are sent before new requests.
+pub(crate) struct Pacer {
+    limit: Option<RateLimit>,
+    tokens: f64,
+    refilled: Instant,
+    prioritize_cancels: bool,
+    cancels: VecDeque<Request>,
+    replay: VecDeque<Request>,
+    requests: VecDeque<Request>,
+    /// Queued requests that had to wait for the rate limit, counted as
+    /// `throttled` when sent
+    delayed: usize,
+    metrics: Arc<WriterMetrics>,
+}
+
+impl Pacer {
+    pub(crate) fn new(limit: Option<RateLimit>, prioritize_cancels: bool, metrics: Arc<WriterMetrics>) -> Pacer {
+        Pacer {
+            limit,
+            tokens: limit.map_or(0.0, |l| l.burst as f64),
+            refilled: Instant::now(),
+            prioritize_cancels,
+            cancels: VecDeque::new(),
+            replay: VecDeque::new(),
+            requests: VecDeque::new(),
+            delayed: 0,
+            metrics,
+        }
+    }
+
+    pub(crate) fn push(&mut self, request: Request) {
+        let jump = self.prioritize_cancels && is_cancel(&request)
+            && !self.queued().any(|queued| cancels(&request, queued));
+
+        if jump {
+            self.cancels.push_back(request);
+        } else {
+            self.requests.push_back(request);
+        }
+
+        self.update_depth();
+    }
+
+    /// Queues a request that failed to send ahead of new requests, it is
+    /// sent after the replayed subscriptions
+    pub(crate) fn retry(&mut self, request: Request) {
+        self.requests.push_front(request);
+        self.update_depth();
+    }
+
+    /// Replaces previously replayed requests that were not sent yet.
+    ///
+    /// A replayed subscription that is cancelled by a queued request, e.g. one
+    /// cancelled while disconnected, is dropped along with the cancel. The
+    /// dropped cancels are returned.
+    pub(crate) fn replay(&mut self, requests: Vec<Request>) -> Vec<Request> {
+        let mut cancelled = Vec::new();
+        let mut replay = VecDeque::new();
+
+        for request in requests {
+            match self.take_cancel(&request) {
+                Some(cancel) => cancelled.push(cancel),
+                None => replay.push_back(request),
+            }
+        }
+
+        self.replay = replay;
+        self.delayed = self.delayed.min(self.len());
+        self.update_depth();
+        cancelled
+    }
+
+    /// Removes the queued cancel of `request`, unless it is meant for a
+    /// request queued ahead of it
+    fn take_cancel(&mut self, request: &Request) -> Option<Request> {
+        if let Some(i) = self.cancels.iter().position(|cancel| cancels(cancel, request)) {
+            return self.cancels.remove(i);
+        }
+
+        let i = self.requests.iter().position(|cancel| cancels(cancel, request))?;
+        if self.requests.iter().take(i).any(|queued| cancels(&self.requests[i], queued)) {
+            return None;
+        }
+        self.requests.remove(i)
+    }
+
+    /// Fires when the next queued request may be sent, never if the queue is empty
+    pub(crate) fn ready(&mut self) -> Receiver<Instant> {
+        match self.wait_time() {
+            Some(wait) => after(wait),
+            None => never(),
+        }
+    }
+
+    /// Time until the next queued request may be sent, None if the queue is empty
+    pub(crate) fn wait_time(&mut self) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Some(Duration::default()),
+        };
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst.max(1) as f64);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            Some(Duration::default())
+        } else {
+            self.delayed = self.len();
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate))
+        }
+    }
+
+    /// Takes the next request if the rate limit allows it
+    pub(crate) fn pop(&mut self) -> Option<Request> {
+        if self.wait_time()? > Duration::default() {
+            return None;
+        }
+
+        let request = self.cancels.pop_front()
+            .or_else(|| self.replay.pop_front())
+            .or_else(|| self.requests.pop_front())?;
+
+        if self.limit.is_some() {
+            self.tokens -= 1.0;
+        }
+        if self.delayed > 0 {
+            self.delayed -= 1;
+            self.metrics.throttled.fetch_add(1, Ordering::Relaxed);
+        }
+        self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+        self.update_depth();
+
+        Some(request)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn len(&self) -> usize {
+        self.cancels.len() + self.replay.len() + self.requests.len()
+    }
+
+    fn queued(&self) -> impl Iterator<Item=&Request> {
+        self.replay.iter().chain(self.requests.iter())
+    }
+
+    fn update_depth(&self) {
+        let depth = self.len();
+        self.metrics.queue_depth.store(depth, Ordering::Relaxed);
+        self.metrics.max_queue_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+}
+
+fn is_cancel(request: &Request) -> bool {
+    matches!(request,
+        Request::CancelMktData { .. } |
+        Request::CancelMktDepth { .. } |
+        Request::CancelScannerSubscription { .. } |
+        Request::CancelHistoricalData { .. } |
+        Request::CancelRealTimeBars { .. } |
+        Request::CancelTickByTickData { .. } |
+        Request::CancelPositions |
+        Request::ReqAcctData { subscribe: false, .. })
+}
+
+/// True if `cancel` cancels the subscription started by `request`
+fn cancels(cancel: &Request, request: &Request) -> bool {
+    match (cancel, request) {
+        (Request::CancelMktData { ticker_id: a }, Request::ReqMktData { ticker_id: b, .. }) => a == b,
+        (Request::CancelMktDepth { ticker_id: a, .. }, Request::ReqMktDepth { ticker_id: b, .. }) => a == b,
+        (Request::CancelScannerSubscription { ticker_id: a }, Request::ReqScannerSubscription { ticker_id: b, .. }) => a == b,
+        (Request::CancelHistoricalData { ticker_id: a }, Request::ReqHistoricalData { ticker_id: b, .. }) => a == b,
+        (Request::CancelRealTimeBars { ticker_id: a }, Request::ReqRealTimeBars { ticker_id: b, .. }) => a == b,
+        (Request::CancelTickByTickData { req_id: a }, Request::ReqTickByTickData { req_id: b, .. }) => a == b,
+        (Request::CancelPositions, Request::ReqPositions) => true,
+        (Request::ReqAcctData { subscribe: false, .. }, Request::ReqAcctData { subscribe: true, .. }) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::ib::Request;
+    use super::{is_cancel, Pacer, RateLimit, WriterMetrics};
+
+    fn mkt_data(ticker_id: i32) -> Request {
+        Request::ReqMktData {
+            ticker_id,
+            contract: Default::default(),
+            generic_tick_list: String::new(),
+            snapshot: false,
+            regulatory_snapshot: false,
+            mkt_data_options: Vec::new(),
+        }
+    }
+
+    /// Queued requests in the order they are sent, as (is cancel, id)
+    fn drain(pacer: &mut Pacer) -> Vec<(bool, i32)> {
+        std::iter::from_fn(|| pacer.pop())
+            .map(|request| (is_cancel(&request), request.id().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        let metrics = Arc::new(WriterMetrics::default());
+        let mut pacer = Pacer::new(Some(RateLimit { rate: 10.0, burst: 2 }), false, metrics.clone());
+
+        for ticker_id in 1..=3 {
+            pacer.push(mkt_data(ticker_id));
+        }
+
+        assert!(pacer.pop().is_some());
+        assert!(pacer.pop().is_some());
+        assert!(pacer.pop().is_none());
+
+        let wait = pacer.wait_time().unwrap();
+        assert!(wait > Duration::from_millis(50) && wait <= Duration::from_millis(100), "{:?}", wait);
+
+        thread::sleep(wait);
+        assert!(pacer.pop().is_some());
+        assert_eq!(pacer.wait_time(), None);
+
+        let stats = metrics.stats();
+        assert_eq!((stats.sent, stats.throttled, stats.queue_depth, stats.max_queue_depth), (3, 1, 0, 3));
+    }
+
+    #[test]
+    fn only_requests_that_waited_are_throttled() {
+        let metrics = Arc::new(WriterMetrics::default());
+        let mut pacer = Pacer::new(Some(RateLimit { rate: 100.0, burst: 2 }), false, metrics.clone());
+
+        pacer.push(mkt_data(1));
+        pacer.push(mkt_data(2));
+        assert!(pacer.pop().is_some());
+        assert!(pacer.pop().is_some());
+
+        // Refilled before it was due
+        pacer.push(mkt_data(3));
+        thread::sleep(Duration::from_millis(20));
+        assert!(pacer.pop().is_some());
+
+        assert_eq!(metrics.stats().throttled, 0);
+    }
+
+    #[test]
+    fn cancels_jump_ahead_unless_their_request_is_queued() {
+        let mut pacer = Pacer::new(None, true, Default::default());
+
+        pacer.push(mkt_data(1));
+        pacer.push(mkt_data(2));
+        pacer.push(Request::CancelMktData { ticker_id: 3 });
+        pacer.push(Request::CancelMktData { ticker_id: 2 });
+
+        assert_eq!(drain(&mut pacer), [(true, 3), (false, 1), (false, 2), (true, 2)]);
+    }
+
+    #[test]
+    fn cancels_keep_their_place_without_priority() {
+        let mut pacer = Pacer::new(None, false, Default::default());
+
+        pacer.push(mkt_data(1));
+        pacer.push(Request::CancelMktData { ticker_id: 3 });
+
+        assert_eq!(drain(&mut pacer), [(false, 1), (true, 3)]);
+    }
+
+    #[test]
+    fn replay_then_retries_then_new_requests() {
+        let mut pacer = Pacer::new(None, true, Default::default());
+
+        pacer.push(mkt_data(1));
+        pacer.retry(mkt_data(2));
+        pacer.replay(vec![mkt_data(3), mkt_data(4)]);
+        pacer.replay(vec![mkt_data(5)]);
+
+        assert_eq!(drain(&mut pacer), [(false, 5), (false, 2), (false, 1)]);
+    }
+
+    #[test]
+    fn replay_skips_subscriptions_cancelled_meanwhile() {
+        for prioritize_cancels in [true, false] {
+            let mut pacer = Pacer::new(None, prioritize_cancels, Default::default());
+
+            pacer.push(Request::CancelMktData { ticker_id: 1 });
+            pacer.push(Request::CancelMktData { ticker_id: 2 });
+            pacer.push(mkt_data(2));
+            pacer.push(Request::CancelMktData { ticker_id: 2 });
+
+            let cancelled = pacer.replay(vec![mkt_data(1), mkt_data(2), mkt_data(3)]);
+
+            assert_eq!(cancelled.iter().map(|cancel| cancel.id().unwrap()).collect::<Vec<_>>(), [1, 2]);
+            assert_eq!(drain(&mut pacer), [(false, 3), (false, 2), (true, 2)]);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! In-memory `Transport` and a fake gateway for tests
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::protocol;
+
+use super::{ConnectOptions, Socket, Transport};
+
+/// One direction of a pipe. Reads block until data arrives or the pipe is
+/// closed.
+#[derive(Default)]
+struct Buffer {
+    state: Mutex<BufferState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct BufferState {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Buffer {
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        while state.data.is_empty() && !state.closed {
+            state = self.readable.wait(state).unwrap();
+        }
+
+        let len = buf.len().min(state.data.len());
+        for (byte, data) in buf.iter_mut().zip(state.data.drain(..len)) {
+            *byte = data;
+        }
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        state.data.extend(buf);
+        self.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// A connected pair of in-memory streams
+pub(crate) fn pipe() -> (PipeEnd, PipeEnd) {
+    let (a, b) = (Arc::new(Buffer::default()), Arc::new(Buffer::default()));
+    (PipeEnd { incoming: a.clone(), outgoing: b.clone() }, PipeEnd { incoming: b, outgoing: a })
+}
+
+/// Dropping an end closes the pipe in both directions, like closing a
+/// socket: the other end reads EOF once it has read everything written
+/// before, and its writes fail with `BrokenPipe`.
+pub(crate) struct PipeEnd {
+    incoming: Arc<Buffer>,
+    outgoing: Arc<Buffer>,
+}
+
+/// Reads from a `PipeEnd`, see `Transport::reader`
+pub(crate) struct PipeReader(Arc<Buffer>);
+
+impl Read for PipeEnd {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.incoming.read(buf)
+    }
+}
+
+impl Write for PipeEnd {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let _ = Transport::shutdown(self);
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Transport for PipeEnd {
+    type Reader = PipeReader;
+
+    fn reader(&self) -> io::Result<PipeReader> {
+        Ok(PipeReader(self.incoming.clone()))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.incoming.close();
+        self.outgoing.close();
+        Ok(())
+    }
+}
+
+/// Server side of a connection: answers the handshake and exchanges frames
+/// as lists of fields. Panics on I/O errors.
+pub(crate) struct FakeGateway<S> {
+    stream: S,
+    /// Fields of the START_API message received in the handshake
+    pub(crate) start_api: Vec<String>,
+}
+
+impl<S: Read + Write> FakeGateway<S> {
+    /// Answers the handshake on `stream` with `server_version`
+    pub(crate) fn accept(mut stream: S, server_version: u64) -> FakeGateway<S> {
+        let mut prefix = [0; 4];
+        stream.read_exact(&mut prefix).unwrap();
+        assert_eq!(&prefix, b"API\0");
+        protocol::read_frame(&mut stream).unwrap(); // client versions
+
+        let mut gateway = FakeGateway { stream, start_api: Vec::new() };
+        gateway.send(&[&server_version.to_string(), "20240102 09:30:00 EST"]);
+        gateway.start_api = gateway.recv();
+        gateway
+    }
+
+    pub(crate) fn recv(&mut self) -> Vec<String> {
+        self.try_recv().unwrap()
+    }
+
+    /// Fails once the client closed the connection
+    pub(crate) fn try_recv(&mut self) -> io::Result<Vec<String>> {
+        Ok(protocol::frame_fields(&protocol::read_frame(&mut self.stream)?))
+    }
+
+    pub(crate) fn send(&mut self, fields: &[&str]) {
+        let frame: Vec<u8> = fields.iter().flat_map(|f| f.bytes().chain(Some(0))).collect();
+        self.stream.write_all(&(frame.len() as u32).to_be_bytes()).unwrap();
+        self.stream.write_all(&frame).unwrap();
+    }
+}
+
+/// Connects a `Socket` to a `FakeGateway` of `server_version` over a pipe
+pub(crate) fn connect(options: &ConnectOptions, server_version: u64) -> (Socket, FakeGateway<PipeEnd>) {
+    let (client, server) = pipe();
+    let gateway = thread::spawn(move|| FakeGateway::accept(server, server_version));
+    let socket = options.connect_transport(client).unwrap();
+
+    (socket, gateway.join().unwrap())
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use crate::ib::Request;
+
+/// Streaming requests that are still active, recorded from outgoing requests
+/// so they can be sent again after reconnecting.
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    /// Market data, depth, real-time bars, tick-by-tick, scanners and
+    /// historical data kept up to date by id
+    streams: BTreeMap<i32, Request>,
+    acct_data: Option<Request>,
+    positions: bool,
+    auto_open_orders: Option<Request>,
+    open_orders: Option<Request>,
+}
+
+impl Subscriptions {
+    pub(crate) fn record(&mut self, request: &Request) {
+        match request {
+            Request::ReqMktData { snapshot: true, .. } |
+            Request::ReqMktData { regulatory_snapshot: true, .. } => {},
+            Request::ReqMktData { ticker_id, .. } |
+            Request::ReqMktDepth { ticker_id, .. } |
+            Request::ReqRealTimeBars { ticker_id, .. } |
+            Request::ReqScannerSubscription { ticker_id, .. } |
+            Request::ReqHistoricalData { ticker_id, keep_up_to_date: true, .. } |
+            Request::ReqTickByTickData { req_id: ticker_id, .. } => {
+                self.streams.insert(*ticker_id, request.clone());
+            },
+            Request::CancelMktData { ticker_id } |
+            Request::CancelMktDepth { ticker_id, .. } |
+            Request::CancelRealTimeBars { ticker_id } |
+            Request::CancelScannerSubscription { ticker_id } |
+            Request::CancelHistoricalData { ticker_id } |
+            Request::CancelTickByTickData { req_id: ticker_id } => {
+                self.streams.remove(ticker_id);
+            },
+            Request::ReqAcctData { subscribe: true, .. } => self.acct_data = Some(request.clone()),
+            Request::ReqAcctData { subscribe: false, .. } => self.acct_data = None,
+            Request::ReqPositions => self.positions = true,
+            Request::CancelPositions => self.positions = false,
+            Request::ReqAutoOpenOrders { .. } => self.auto_open_orders = Some(request.clone()),
+            Request::ReqOpenOrders | Request::ReqAllOpenOrders => self.open_orders = Some(request.clone()),
+            _ => {},
+        }
+    }
+
+    /// True if `record` keeps track of the request, i.e. it starts or stops a
+    /// subscription that is replayed after reconnecting
+    pub(crate) fn is_replayed(request: &Request) -> bool {
+        match request {
+            Request::ReqMktData { snapshot, regulatory_snapshot, .. } => !snapshot && !regulatory_snapshot,
+            Request::ReqHistoricalData { keep_up_to_date, .. } => *keep_up_to_date,
+            Request::ReqMktDepth { .. } |
+            Request::ReqRealTimeBars { .. } |
+            Request::ReqScannerSubscription { .. } |
+            Request::ReqTickByTickData { .. } |
+            Request::CancelMktData { .. } |
+            Request::CancelMktDepth { .. } |
+            Request::CancelRealTimeBars { .. } |
+            Request::CancelScannerSubscription { .. } |
+            Request::CancelHistoricalData { .. } |
+            Request::CancelTickByTickData { .. } |
+            Request::ReqAcctData { .. } |
+            Request::ReqPositions |
+            Request::CancelPositions |
+            Request::ReqAutoOpenOrders { .. } |
+            Request::ReqOpenOrders |
+            Request::ReqAllOpenOrders => true,
+            _ => false,
+        }
+    }
+
+    /// Requests to send after START_API on a new connection
+    pub(crate) fn replay(&self) -> Vec<Request> {
+        let mut requests = Vec::new();
+
+        requests.extend(self.acct_data.clone());
+        if self.positions {
+            requests.push(Request::ReqPositions);
+        }
+        requests.extend(self.auto_open_orders.clone());
+        requests.extend(self.open_orders.clone());
+        requests.extend(self.streams.values().cloned());
+
+        requests
+    }
+}
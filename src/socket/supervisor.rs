@@ -0,0 +1,340 @@
+use std::io::BufReader;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{after, bounded, select, Receiver};
+use log::{error, info, warn};
+
+use crate::ib::{ErrorCode, Message, Request};
+use crate::protocol;
+
+use super::{read_loop, unsupported_request, ConnectError, ConnectOptions, Transport};
+use super::envelope::EnvelopeSender;
+use super::pacer::Pacer;
+use super::subscriptions::Subscriptions;
+
+/// A live connection and its reader thread. `closed` disconnects when the
+/// reader thread exits.
+pub(super) struct Connection<T: Transport> {
+    stream: T,
+    pub(super) server_version: u64,
+    closed: Receiver<()>,
+}
+
+impl<T: Transport> Connection<T> {
+    /// Performs the handshake on a transport from `open`
+    pub(super) fn open<F>(options: &ConnectOptions, open: &mut F, messages: &EnvelopeSender) -> Result<Connection<T>, ConnectError>
+        where F: FnMut() -> Result<T, ConnectError>
+    {
+        let mut stream = open()?;
+        let server_version = options.handshake(&mut stream)?;
+
+        let reader = BufReader::new(stream.reader()?);
+        let tx = messages.clone();
+        let (closed_tx, closed_rx) = bounded::<()>(0);
+
+        thread::Builder::new()
+            .name(options.reader_thread_name.clone())
+            .spawn(move|| {
+                read_loop(reader, tx, server_version);
+                drop(closed_tx);
+            })?;
+
+        Ok(Connection { stream, server_version, closed: closed_rx })
+    }
+}
+
+impl<T: Transport> Drop for Connection<T> {
+    fn drop(&mut self) {
+        // Unblocks the reader thread
+        let _ = self.stream.shutdown();
+    }
+}
+
+/// Writer thread of a supervised `Socket`. Writes requests, records
+/// subscriptions, and reconnects when either side of the connection fails.
+///
+/// Exits when the `Socket` or the message channel is dropped.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn supervise<T, F>(options: ConnectOptions, mut open: F, mut conn: Connection<T>, requests: Receiver<Request>, dropped: Receiver<()>, messages: EnvelopeSender, connected: Arc<AtomicBool>, mut pacer: Pacer)
+    where T: Transport, F: FnMut() -> Result<T, ConnectError>
+{
+    let mut subscriptions = Subscriptions::default();
+
+    loop {
+        let lost = select! {
+            recv(requests) -> request => match request {
+                Ok(request) => {
+                    pacer.push(request);
+                    false
+                },
+                Err(_) => return, // Socket dropped
+            },
+            recv(dropped) -> _ => return,
+            recv(pacer.ready()) -> _ => match pacer.pop() {
+                Some(request) => {
+                    subscriptions.record(&request);
+                    match write(&mut conn, &request, &messages) {
+                        Ok(()) => false,
+                        Err(()) => {
+                            resend_or_report(request, &mut pacer, &messages);
+                            true
+                        },
+                    }
+                },
+                None => false,
+            },
+            recv(conn.closed) -> _ => true,
+        };
+
+        if lost {
+            connected.store(false, Ordering::Relaxed);
+            drop(conn);
+
+            conn = match reconnect(&options, &mut open, &requests, &dropped, &messages, &mut pacer) {
+                Some(conn) => conn,
+                None => return,
+            };
+            connected.store(true, Ordering::Relaxed);
+
+            info!("Reconnected, replaying subscriptions");
+            for cancel in pacer.replay(subscriptions.replay()) {
+                subscriptions.record(&cancel);
+            }
+        }
+    }
+}
+
+/// Fails if the connection is lost
+fn write<T: Transport>(conn: &mut Connection<T>, request: &Request, messages: &EnvelopeSender) -> Result<(), ()> {
+    match protocol::to_writer(&mut conn.stream, request, conn.server_version) {
+        Ok(()) => Ok(()),
+        Err(protocol::Error::Io(err)) => {
+            error!("Write error: {}", err);
+            warn!("Request not sent: {:?}", request);
+            Err(())
+        },
+        Err(err) => {
+            error!("Cannot encode {:?}: {}", request, err);
+            if let Some(msg) = unsupported_request(request, &err) {
+                let _ = messages.send_local(msg);
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Deals with a request that failed to write because the connection dropped.
+/// Subscriptions and their cancels are covered by the replay after
+/// reconnecting. Orders are reported as not sent, since part of the message
+/// may have reached TWS. Everything else is sent again after reconnecting.
+fn resend_or_report(request: Request, pacer: &mut Pacer, messages: &EnvelopeSender) {
+    if Subscriptions::is_replayed(&request) {
+        return;
+    }
+
+    match request {
+        Request::PlaceOrder { order_id, .. } => {
+            let _ = messages.send_local(Message::ErrMsg {
+                version: 2,
+                id: order_id,
+                error_code: ErrorCode::FailSendOrder,
+                error_msg: "Order not sent, connection lost".into(),
+            });
+        },
+        request => pacer.retry(request),
+    }
+}
+
+/// Retries with exponential backoff until connected, queueing requests sent
+/// in the meantime. Returns None if the `Socket` or the message channel is
+/// dropped.
+fn reconnect<T, F>(options: &ConnectOptions, open: &mut F, requests: &Receiver<Request>, dropped: &Receiver<()>, messages: &EnvelopeSender, pacer: &mut Pacer) -> Option<Connection<T>>
+    where T: Transport, F: FnMut() -> Result<T, ConnectError>
+{
+    messages.send_local(Message::Disconnected).ok()?;
+
+    let mut delay = options.reconnect_min_delay;
+
+    loop {
+        let retry = after(delay);
+        loop {
+            select! {
+                recv(requests) -> request => match request {
+                    Ok(request) => pacer.push(request),
+                    Err(_) => return None,
+                },
+                recv(dropped) -> _ => return None,
+                recv(retry) -> _ => break,
+            }
+        }
+
+        match Connection::open(options, open, messages) {
+            Ok(conn) => {
+                messages.send_local(Message::Reconnected { server_version: conn.server_version }).ok()?;
+                return Some(conn);
+            },
+            Err(err) => {
+                warn!("Reconnect failed: {}, retrying in {:?}", err, delay);
+                delay = next_delay(delay, options.reconnect_max_delay);
+            }
+        }
+    }
+}
+
+fn next_delay(delay: Duration, max: Duration) -> Duration {
+    (delay * 2).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crossbeam_channel::unbounded;
+
+    use crate::ib::{Message, Request};
+    use super::super::{ConnectOptions, Socket};
+    use super::super::pipe::{pipe, FakeGateway};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn recv(socket: &Socket) -> Message {
+        socket.rx.recv_timeout(TIMEOUT).unwrap().into_message()
+    }
+
+    fn mkt_data(ticker_id: i32) -> Request {
+        Request::ReqMktData {
+            ticker_id,
+            contract: Default::default(),
+            generic_tick_list: String::new(),
+            snapshot: false,
+            regulatory_snapshot: false,
+            mkt_data_options: Vec::new(),
+        }
+    }
+
+    fn options(min_delay: Duration) -> ConnectOptions {
+        ConnectOptions::new().reconnect_backoff(min_delay, min_delay * 4)
+    }
+
+    #[test]
+    fn reconnects_and_replays_subscriptions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = thread::spawn(move|| {
+            let mut first = FakeGateway::accept(listener.accept().unwrap().0, 151);
+            let subscription = first.recv();
+            drop(first);
+
+            let mut second = FakeGateway::accept(listener.accept().unwrap().0, 151);
+            (subscription, second.recv(), second.recv(), second)
+        });
+
+        let socket = options(Duration::from_millis(10)).connect_supervised(addr).unwrap();
+        assert!(matches!(recv(&socket), Message::Connected { server_version: 151 }));
+
+        socket.request(mkt_data(1)).unwrap();
+
+        assert!(matches!(recv(&socket), Message::Disconnected));
+        socket.request(Request::ReqCurrentTime).unwrap();
+        assert!(matches!(recv(&socket), Message::Reconnected { server_version: 151 }));
+
+        let (subscription, replayed, queued, _gateway) = gateway.join().unwrap();
+        assert_eq!(subscription[..3], ["1", "11", "1"]);
+        assert_eq!(replayed, subscription);
+        assert_eq!(queued, ["49", "1"]);
+        assert!(socket.is_connected());
+    }
+
+    #[test]
+    fn subscriptions_cancelled_while_disconnected_are_not_replayed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let gateway = thread::spawn(move|| {
+            let mut first = FakeGateway::accept(listener.accept().unwrap().0, 151);
+            first.recv();
+            first.recv();
+            drop(first);
+
+            let mut second = FakeGateway::accept(listener.accept().unwrap().0, 151);
+            (second.recv(), second.recv(), second)
+        });
+
+        let socket = options(Duration::from_millis(200)).connect_supervised(addr).unwrap();
+        assert!(matches!(recv(&socket), Message::Connected { .. }));
+
+        socket.request(mkt_data(1)).unwrap();
+        socket.request(mkt_data(2)).unwrap();
+        assert!(matches!(recv(&socket), Message::Disconnected));
+
+        socket.request(Request::CancelMktData { ticker_id: 1 }).unwrap();
+        socket.request(Request::ReqCurrentTime).unwrap();
+        assert!(matches!(recv(&socket), Message::Reconnected { .. }));
+
+        let (replayed, queued, _gateway) = gateway.join().unwrap();
+        assert_eq!(replayed[..3], ["1", "11", "2"]);
+        assert_eq!(queued, ["49", "1"]);
+    }
+
+    #[test]
+    fn dropping_the_socket_stops_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let socket = thread::scope(|scope| {
+            let gateway = scope.spawn(|| FakeGateway::accept(listener.accept().unwrap().0, 151));
+            let socket = options(Duration::from_millis(200)).connect_supervised(addr).unwrap();
+            drop(gateway.join().unwrap());
+            socket
+        });
+
+        assert!(matches!(recv(&socket), Message::Connected { .. }));
+        assert!(matches!(recv(&socket), Message::Disconnected));
+        assert!(!socket.is_connected());
+        drop(socket);
+
+        thread::sleep(Duration::from_millis(400));
+        listener.set_nonblocking(true).unwrap();
+        assert_eq!(listener.accept().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn reconnects_over_any_transport() {
+        let (servers_tx, servers) = unbounded();
+
+        let gateway = thread::spawn(move|| {
+            let mut first = FakeGateway::accept(servers.recv().unwrap(), 151);
+            first.recv();
+            drop(first);
+
+            let mut second = FakeGateway::accept(servers.recv().unwrap(), 151);
+            (second.recv(), second)
+        });
+
+        let socket = options(Duration::from_millis(10)).connect_supervised_with(move|| {
+            let (client, server) = pipe();
+            servers_tx.send(server).map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+            Ok(client)
+        }).unwrap();
+        assert!(matches!(recv(&socket), Message::Connected { .. }));
+
+        socket.request(Request::ReqCurrentTime).unwrap();
+        assert!(matches!(recv(&socket), Message::Disconnected));
+        assert!(matches!(recv(&socket), Message::Reconnected { .. }));
+        socket.request(Request::ReqCurrentTime).unwrap();
+
+        let (request, mut gateway) = gateway.join().unwrap();
+        assert_eq!(request, ["49", "1"]);
+
+        // The connection is shut down with the `Socket`
+        drop(socket);
+        assert!(gateway.try_recv().is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// A duplex stream to TWS / IB Gateway, e.g. a TCP or Unix domain socket or
+/// an in-memory pipe to a fake gateway in tests.
+///
+/// The handshake is done on the transport itself. Afterwards it is written to
+/// by the writer thread, while `reader` is read from by the reader thread, so
+/// reads must not wait for writes or the other way round. TLS streams, which
+/// need exclusive access for both, don't qualify.
+pub trait Transport: Read + Write + Send + 'static {
+    type Reader: Read + Send + 'static;
+
+    /// Handle reading from the same stream, like `TcpStream::try_clone`
+    fn reader(&self) -> io::Result<Self::Reader>;
+
+    /// Bounds reads during the handshake. Transports without read timeouts
+    /// can ignore it, the handshake then waits indefinitely.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let _ = timeout;
+        Ok(())
+    }
+
+    /// Closes both directions, so the reader thread returns from a blocking
+    /// read. Transports that can't do this are closed when all their handles
+    /// are dropped.
+    fn shutdown(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = TcpStream;
+
+    fn reader(&self) -> io::Result<TcpStream> {
+        self.try_clone()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    type Reader = UnixStream;
+
+    fn reader(&self) -> io::Result<UnixStream> {
+        self.try_clone()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
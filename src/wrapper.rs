@@ -0,0 +1,230 @@
+//! Callback interface in the style of the official `EWrapper`.
+//!
+//! Implement the methods of `Wrapper` for the messages of interest and pass
+//! incoming messages to `dispatch`, or let `run` read them from a `Socket`.
+//! Methods are named after their `EWrapper` counterparts where there is one.
+
+use crossbeam_channel::Receiver;
+
+use crate::ib::{Bar, Contract, ContractDetails, ErrorCode, Message, Order, OrderState, ScanData, TickByTick};
+use crate::socket::Socket;
+
+/// One method per `Message`, all doing nothing by default
+#[allow(unused_variables)]
+pub trait Wrapper {
+    /// `size` is 0 for ticks without a size
+    fn tick_price(&mut self, ticker_id: i32, tick_type: i32, price: f64, size: i64, attr_mask: i32) {}
+    fn tick_size(&mut self, ticker_id: i32, tick_type: i32, size: i64) {}
+    fn tick_generic(&mut self, ticker_id: i32, tick_type: i32, value: f64) {}
+    fn tick_string(&mut self, ticker_id: i32, tick_type: i32, value: &str) {}
+    fn tick_snapshot_end(&mut self, req_id: i32) {}
+    fn tick_req_params(&mut self, ticker_id: i32, min_tick: f64, bbo_exchange: &str, snapshot_permissions: i32) {}
+    fn market_data_type(&mut self, req_id: i32, market_data_type: i32) {}
+    fn tick_by_tick(&mut self, req_id: i32, tick: &TickByTick) {}
+
+    #[allow(clippy::too_many_arguments)]
+    fn order_status(&mut self, order_id: i32, status: &str, filled: f64, remaining: f64, avg_fill_price: f64, perm_id: i32, parent_id: i32, last_fill_price: f64, client_id: i32, why_held: &str, mkt_cap_price: f64) {}
+    fn open_order(&mut self, order_id: i32, contract: &Contract, order: &Order, state: &OrderState) {}
+    fn open_order_end(&mut self) {}
+    fn next_valid_id(&mut self, order_id: i32) {}
+
+    /// `id` is a request or order id, or -1 for notices about the connection
+    fn error(&mut self, id: i32, error_code: ErrorCode, error_msg: &str) {}
+
+    fn update_account_value(&mut self, key: &str, val: &str, currency: &str, account_name: &str) {}
+    #[allow(clippy::too_many_arguments)]
+    fn update_portfolio(&mut self, contract: &Contract, position: f64, market_price: f64, market_value: f64, average_cost: f64, unrealized_pnl: f64, realized_pnl: f64, account_name: &str) {}
+    fn update_account_time(&mut self, time: &str) {}
+    fn account_download_end(&mut self, account: &str) {}
+    fn managed_accounts(&mut self, accounts_list: &str) {}
+    fn position(&mut self, account: &str, contract: &Contract, position: f64, avg_cost: f64) {}
+    fn position_end(&mut self) {}
+
+    fn contract_details(&mut self, req_id: i32, details: &ContractDetails) {}
+    fn contract_details_end(&mut self, req_id: i32) {}
+
+    fn update_mkt_depth(&mut self, ticker_id: i32, position: i32, operation: i32, side: i32, price: f64, size: i64) {}
+    #[allow(clippy::too_many_arguments)]
+    fn update_mkt_depth_l2(&mut self, ticker_id: i32, position: i32, market_maker: &str, operation: i32, side: i32, price: f64, size: i64, is_smart_depth: bool) {}
+
+    /// Called for each bar, followed by `historical_data_end`
+    fn historical_data(&mut self, req_id: i32, bar: &Bar) {}
+    fn historical_data_end(&mut self, req_id: i32, start: &str, end: &str) {}
+    fn historical_data_update(&mut self, req_id: i32, bar: &Bar) {}
+    fn real_time_bar(&mut self, req_id: i32, bar: &Bar) {}
+
+    /// Called for each row, followed by `scanner_data_end`
+    fn scanner_data(&mut self, req_id: i32, data: &ScanData) {}
+    fn scanner_data_end(&mut self, req_id: i32) {}
+
+    fn current_time(&mut self, time: i64) {}
+
+    /// See `Message::Connected`
+    fn connected(&mut self, server_version: u64) {}
+    /// See `Message::Disconnected`
+    fn disconnected(&mut self) {}
+    /// See `Message::Reconnected`
+    fn reconnected(&mut self, server_version: u64) {}
+
+    fn unknown_message(&mut self, fields: &[String]) {}
+    fn decode_failed(&mut self, error: &str, fields: &[String]) {}
+}
+
+/// Calls the `wrapper` method(s) for `msg`
+pub fn dispatch<W: Wrapper + ?Sized>(wrapper: &mut W, msg: &Message) {
+    use Message::*;
+
+    match msg {
+        TickPrice { ticker_id, tick_type, price, size, attr_mask, .. } => wrapper.tick_price(*ticker_id, *tick_type, *price, *size, *attr_mask),
+        TickSize { ticker_id, tick_type, size, .. } => wrapper.tick_size(*ticker_id, *tick_type, *size),
+        TickGeneric { ticker_id, tick_type, value, .. } => wrapper.tick_generic(*ticker_id, *tick_type, *value),
+        TickString { ticker_id, tick_type, value, .. } => wrapper.tick_string(*ticker_id, *tick_type, value),
+        TickSnapshotEnd { req_id, .. } => wrapper.tick_snapshot_end(*req_id),
+        TickReqParams { ticker_id, min_tick, bbo_exchange, snapshot_permissions } =>
+            wrapper.tick_req_params(*ticker_id, *min_tick, bbo_exchange, *snapshot_permissions),
+        MarketDataType { req_id, market_data_type, .. } => wrapper.market_data_type(*req_id, *market_data_type),
+        TickByTick { req_id, tick } => wrapper.tick_by_tick(*req_id, tick),
+
+        OrderStatus { order_id, status, filled, remaining, avg_fill_price, perm_id, parent_id, last_fill_price, client_id, why_held, mkt_cap_price } =>
+            wrapper.order_status(*order_id, status, *filled, *remaining, *avg_fill_price, *perm_id, *parent_id, *last_fill_price, *client_id, why_held, *mkt_cap_price),
+        OpenOrder(open) => wrapper.open_order(open.order.order_id, &open.contract, &open.order, &open.state),
+        OpenOrderEnd { .. } => wrapper.open_order_end(),
+        NextValidId { order_id, .. } => wrapper.next_valid_id(*order_id),
+
+        ErrMsg { id, error_code, error_msg, .. } => wrapper.error(*id, *error_code, error_msg),
+
+        AcctValue { key, val, cur, account_name, .. } => wrapper.update_account_value(key, val, cur, account_name),
+        PortfolioValue { contract, position, market_price, market_value, average_cost, unrealized_pnl, realized_pnl, account_name, .. } =>
+            wrapper.update_portfolio(&contract.clone().into(), *position, *market_price, *market_value, *average_cost, *unrealized_pnl, *realized_pnl, account_name),
+        AcctTime { account_time, .. } => wrapper.update_account_time(account_time),
+        AcctDownloadEnd { account, .. } => wrapper.account_download_end(account),
+        ManagedAccts { accounts_list, .. } => wrapper.managed_accounts(accounts_list),
+        PositionData { account, contract, position, avg_cost, .. } => wrapper.position(account, contract, *position, *avg_cost),
+        PositionDataEnd { .. } => wrapper.position_end(),
+
+        ContractData { req_id, details, .. } => wrapper.contract_details(*req_id, details),
+        ContractDataEnd { req_id, .. } => wrapper.contract_details_end(*req_id),
+
+        MarketDepth { ticker_id, position, operation, side, price, size, .. } =>
+            wrapper.update_mkt_depth(*ticker_id, *position, *operation, *side, *price, *size),
+        MarketDepthL2 { ticker_id, position, market_maker, operation, side, price, size, is_smart_depth, .. } =>
+            wrapper.update_mkt_depth_l2(*ticker_id, *position, market_maker, *operation, *side, *price, *size, *is_smart_depth),
+
+        HistoricalData { req_id, start, end, bars } => {
+            for bar in bars {
+                wrapper.historical_data(*req_id, bar);
+            }
+            wrapper.historical_data_end(*req_id, start, end);
+        },
+        HistoricalDataUpdate { req_id, bar } => wrapper.historical_data_update(*req_id, bar),
+        RealTimeBars { req_id, bar, .. } => wrapper.real_time_bar(*req_id, bar),
+
+        ScannerData { ticker_id, data, .. } => {
+            for row in data {
+                wrapper.scanner_data(*ticker_id, row);
+            }
+            wrapper.scanner_data_end(*ticker_id);
+        },
+
+        CurrentTime { time, .. } => wrapper.current_time(*time),
+
+        Connected { server_version } => wrapper.connected(*server_version),
+        Disconnected => wrapper.disconnected(),
+        Reconnected { server_version } => wrapper.reconnected(*server_version),
+
+        UnknownMessage(fields) => wrapper.unknown_message(fields),
+        DecodeFailed { error, fields } => wrapper.decode_failed(error, fields),
+    }
+}
+
+/// Dispatches all messages of `socket` until it is closed
+pub fn run<W: Wrapper + ?Sized>(socket: &Socket, wrapper: &mut W) {
+    for envelope in &socket.rx {
+        dispatch(wrapper, &envelope);
+    }
+}
+
+/// Dispatches messages until `messages` disconnects, e.g. `Client::rx`
+pub fn run_messages<W: Wrapper + ?Sized>(messages: &Receiver<Message>, wrapper: &mut W) {
+    for msg in messages {
+        dispatch(wrapper, &msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ib::{Bar, ErrorCode, Message, ScanData};
+    use super::{dispatch, Wrapper};
+
+    /// Records the calls it gets
+    #[derive(Default)]
+    struct Calls(Vec<String>);
+
+    impl Wrapper for Calls {
+        fn error(&mut self, id: i32, error_code: ErrorCode, error_msg: &str) {
+            self.0.push(format!("error {} {:?} {}", id, error_code, error_msg));
+        }
+
+        fn historical_data(&mut self, req_id: i32, bar: &Bar) {
+            self.0.push(format!("historical_data {} {}", req_id, bar.time));
+        }
+
+        fn historical_data_end(&mut self, req_id: i32, start: &str, end: &str) {
+            self.0.push(format!("historical_data_end {} {} {}", req_id, start, end));
+        }
+
+        fn scanner_data(&mut self, req_id: i32, data: &ScanData) {
+            self.0.push(format!("scanner_data {} {}", req_id, data.rank));
+        }
+
+        fn scanner_data_end(&mut self, req_id: i32) {
+            self.0.push(format!("scanner_data_end {}", req_id));
+        }
+    }
+
+    fn bar(time: &str) -> Bar {
+        Bar { time: time.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn rows_then_end() {
+        let mut calls = Calls::default();
+
+        dispatch(&mut calls, &Message::HistoricalData {
+            req_id: 1,
+            start: "20240101".into(),
+            end: "20240103".into(),
+            bars: vec![bar("20240101"), bar("20240102")],
+        });
+        dispatch(&mut calls, &Message::ScannerData {
+            version: 3,
+            ticker_id: 2,
+            data: vec![ScanData { rank: 0, ..Default::default() }, ScanData { rank: 1, ..Default::default() }],
+        });
+
+        assert_eq!(calls.0, [
+            "historical_data 1 20240101",
+            "historical_data 1 20240102",
+            "historical_data_end 1 20240101 20240103",
+            "scanner_data 2 0",
+            "scanner_data 2 1",
+            "scanner_data_end 2",
+        ]);
+    }
+
+    #[test]
+    fn errors_reach_error() {
+        let mut calls = Calls::default();
+
+        dispatch(&mut calls, &Message::ErrMsg {
+            version: 2,
+            id: 3,
+            error_code: ErrorCode::NoSecurityDefinition,
+            error_msg: "No security definition has been found for the request".into(),
+        });
+        // Not an error
+        dispatch(&mut calls, &Message::Disconnected);
+
+        assert_eq!(calls.0, ["error 3 NoSecurityDefinition No security definition has been found for the request"]);
+    }
+}